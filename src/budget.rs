@@ -0,0 +1,238 @@
+use std::fmt;
+
+use crate::git::{Diff, FileChange};
+
+/// Rough token estimate for prompt budgeting. Most tokenizers average around four
+/// characters per token for code and english text.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// How useful a file's diff is to the model, lowest value is elided first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Value {
+    Lockfile,
+    Generated,
+    WhitespaceOnly,
+    Source,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lockfile => write!(f, "lockfile"),
+            Self::Generated => write!(f, "generated"),
+            Self::WhitespaceOnly => write!(f, "whitespace only"),
+            Self::Source => write!(f, "too large"),
+        }
+    }
+}
+
+/// How much the model would learn from this file's changes
+pub fn value(file: &FileChange) -> Value {
+    let name = file.path.rsplit('/').next().unwrap_or(&file.path);
+
+    if LOCKFILES.contains(&name) || name.ends_with(".lock") {
+        Value::Lockfile
+    } else if file.binary
+        || GENERATED_SUFFIXES.iter().any(|s| name.ends_with(s))
+        || GENERATED_DIRS
+            .iter()
+            .any(|d| file.path.starts_with(d) || file.path.contains(&format!("/{d}")))
+    {
+        Value::Generated
+    } else if is_whitespace_only(file) {
+        Value::WhitespaceOnly
+    } else {
+        Value::Source
+    }
+}
+
+fn is_whitespace_only(file: &FileChange) -> bool {
+    let strip = |prefix: char| -> String {
+        file.changed_lines()
+            .filter_map(|l| l.strip_prefix(prefix))
+            .flat_map(str::chars)
+            .filter(|c| !c.is_whitespace())
+            .collect()
+    };
+
+    file.changed_lines().next().is_some() && strip('+') == strip('-')
+}
+
+/// A file which was removed from the diff to fit the token budget
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Elided {
+    pub path: String,
+    pub reason: Value,
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl fmt::Display for Elided {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, +{} -{})",
+            self.path, self.reason, self.added, self.removed
+        )
+    }
+}
+
+/// A diff trimmed down to a token budget
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Budgeted {
+    pub diff: String,
+    pub elided: Vec<Elided>,
+}
+
+/// Drop the lowest value files from `diff` until it fits within `max_tokens`.
+/// Lockfiles go first, then generated code, whitespace only changes and finally
+/// the largest source files.
+pub fn fit_to_budget(diff: &Diff, max_tokens: usize) -> Budgeted {
+    let mut total: usize = diff.files.iter().map(|f| estimate_tokens(&f.patch)).sum();
+    let mut dropped = vec![None; diff.files.len()];
+
+    if total > max_tokens {
+        let mut order: Vec<(usize, Value, usize)> = diff
+            .files
+            .iter()
+            .enumerate()
+            .map(|(i, file)| (i, value(file), estimate_tokens(&file.patch)))
+            .collect();
+        order.sort_by(|a, b| a.1.cmp(&b.1).then(b.2.cmp(&a.2)));
+
+        for (i, value, tokens) in order {
+            if total <= max_tokens {
+                break;
+            }
+            total -= tokens;
+            dropped[i] = Some(value);
+        }
+    }
+
+    let mut budgeted = Budgeted {
+        diff: String::new(),
+        elided: Vec::new(),
+    };
+    for (file, dropped) in diff.files.iter().zip(dropped) {
+        match dropped {
+            Some(reason) => budgeted.elided.push(Elided {
+                path: file.path.clone(),
+                reason,
+                added: file.additions,
+                removed: file.deletions,
+            }),
+            None => budgeted.diff.push_str(&file.patch),
+        }
+    }
+
+    budgeted
+}
+
+const LOCKFILES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "Gemfile.lock",
+    "composer.lock",
+    "go.sum",
+    "uv.lock",
+    "Pipfile.lock",
+];
+
+const GENERATED_SUFFIXES: &[&str] = &[
+    ".min.js",
+    ".min.css",
+    ".map",
+    ".pb.go",
+    "_pb2.py",
+    "_pb2_grpc.py",
+    ".pb.rs",
+    ".g.dart",
+    ".snap",
+    ".svg",
+];
+
+const GENERATED_DIRS: &[&str] = &["dist/", "vendor/", "node_modules/", "__snapshots__/"];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::git::ChangeStatus;
+
+    fn file(path: &str, lines: &[&str]) -> FileChange {
+        let mut patch = format!("diff --git a/{path} b/{path}\n--- a/{path}\n+++ b/{path}\n");
+        for line in lines {
+            patch.push_str(line);
+            patch.push('\n');
+        }
+        FileChange {
+            path: path.to_string(),
+            old_path: None,
+            status: ChangeStatus::Modified,
+            binary: false,
+            additions: lines.iter().filter(|l| l.starts_with('+')).count(),
+            deletions: lines.iter().filter(|l| l.starts_with('-')).count(),
+            hunks: Vec::new(),
+            patch,
+        }
+    }
+
+    fn diff() -> Diff {
+        Diff {
+            files: vec![
+                file(
+                    "Cargo.lock",
+                    &[
+                        "-name = \"anyhow\"",
+                        "-version = \"1.0.97\"",
+                        "+name = \"anyhow\"",
+                        "+version = \"1.0.98\"",
+                    ],
+                ),
+                file("src/fmt.rs", &["-fn  main( ) {}", "+fn main() {}"]),
+                file("src/main.rs", &[" fn main() {}", "+fn helper() {}"]),
+            ],
+            skipped: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_value() {
+        assert_eq!(
+            vec![Value::Lockfile, Value::WhitespaceOnly, Value::Source],
+            diff().files.iter().map(value).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_fit_to_budget_drops_lowest_value_first() {
+        let diff = diff();
+        let main = &diff.files[2].patch;
+        let budgeted = fit_to_budget(&diff, estimate_tokens(main));
+
+        assert_eq!(main, &budgeted.diff);
+        assert_eq!(
+            vec![
+                "Cargo.lock (lockfile, +2 -2)",
+                "src/fmt.rs (whitespace only, +1 -1)"
+            ],
+            budgeted
+                .elided
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+
+        let unchanged = fit_to_budget(&diff, usize::MAX);
+        assert_eq!(diff.to_patch(), unchanged.diff);
+        assert!(unchanged.elided.is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use indoc::indoc;
+
+/// Preamble for a changelog created from scratch
+pub const HEADER: &str = indoc! {"
+    # Changelog
+
+    All notable changes to this project will be documented in this file.
+
+    The format is based on [Keep a Changelog](https://keepachangelog.com/en/1.1.0/).
+
+"};
+
+/// Heading of the section for changes which are not released yet
+pub const UNRELEASED: &str = "Unreleased";
+
+/// The version a section is written for: the one given, otherwise the tag being released.
+/// Notes up to `HEAD` describe changes which have no tag yet.
+pub fn version<'a>(version: Option<&'a str>, to: &'a str) -> &'a str {
+    match version {
+        Some(version) => version,
+        None if to == "HEAD" => UNRELEASED,
+        None => to,
+    }
+}
+
+/// A release section: the `## [version] - date` heading followed by the generated notes.
+/// The `[Unreleased]` section has no date.
+pub fn section(version: &str, date: &str, notes: &str) -> String {
+    if version == UNRELEASED {
+        return format!("## [{UNRELEASED}]\n\n{}\n", notes.trim());
+    }
+
+    // Tags are usually `v1.2.3` while Keep a Changelog headings use the bare version
+    let version = match version.strip_prefix('v') {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => rest,
+        _ => version,
+    };
+    format!("## [{version}] - {date}\n\n{}\n", notes.trim())
+}
+
+/// Insert a release section above the latest release, keeping any `[Unreleased]`
+/// section at the top. A new `[Unreleased]` section replaces the existing one.
+pub fn prepend(changelog: &str, section: &str) -> String {
+    let unreleased = is_unreleased(section);
+    let mut offset = 0;
+    let mut replacing = None;
+    for line in changelog.split_inclusive('\n') {
+        if line.starts_with("## ") {
+            if let Some(start) = replacing {
+                return format!("{}{section}\n{}", &changelog[..start], &changelog[offset..]);
+            }
+            if unreleased && is_unreleased(line) {
+                replacing = Some(offset);
+            } else if unreleased || !is_unreleased(line) {
+                return format!(
+                    "{}{section}\n{}",
+                    &changelog[..offset],
+                    &changelog[offset..]
+                );
+            }
+        }
+        offset += line.len();
+    }
+    if let Some(start) = replacing {
+        return format!("{}{section}", &changelog[..start]);
+    }
+
+    // No previous releases, append after whatever preamble exists
+    let mut changelog = changelog.to_string();
+    if !changelog.is_empty() && !changelog.ends_with("\n\n") {
+        changelog.push_str(if changelog.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        });
+    }
+    changelog + section
+}
+
+fn is_unreleased(heading: &str) -> bool {
+    heading
+        .strip_prefix("## ")
+        .is_some_and(|rest| rest.trim_start().starts_with(&format!("[{UNRELEASED}]")))
+}
+
+/// Prepend a release section into the changelog at `path`, creating it when missing
+pub fn write(path: &Path, section: &str) -> Result<()> {
+    let existing = match std::fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::from(HEADER),
+        Err(e) => return Err(e).with_context(|| format!("Unable to read `{}`", path.display())),
+    };
+
+    std::fs::write(path, prepend(&existing, section))
+        .with_context(|| format!("Unable to write `{}`", path.display()))
+}
+
+/// Format seconds since the unix epoch as a `YYYY-MM-DD` UTC date
+pub fn date(seconds: i64) -> String {
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let days = seconds.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_date() {
+        assert_eq!("1970-01-01", date(0));
+        assert_eq!("2024-02-29", date(1_709_208_000));
+        assert_eq!("2025-12-31", date(1_767_225_599));
+    }
+
+    #[test]
+    fn test_prepend() {
+        let section = section("v0.4.0", "2025-06-01", "### Added\n- Release notes\n");
+        assert_eq!(
+            "## [0.4.0] - 2025-06-01\n\n### Added\n- Release notes\n",
+            section
+        );
+
+        let changelog = indoc! {"
+            # Changelog
+
+            ## [Unreleased]
+
+            ## [0.3.1] - 2025-05-01
+
+            ### Fixed
+            - Lockfiles
+        "};
+        assert_eq!(
+            indoc! {"
+                # Changelog
+
+                ## [Unreleased]
+
+                ## [0.4.0] - 2025-06-01
+
+                ### Added
+                - Release notes
+
+                ## [0.3.1] - 2025-05-01
+
+                ### Fixed
+                - Lockfiles
+            "},
+            prepend(changelog, &section)
+        );
+
+        assert_eq!(format!("{HEADER}{section}"), prepend(HEADER, &section));
+    }
+
+    #[test]
+    fn test_unreleased() {
+        assert_eq!("v0.3.1", version(None, "v0.3.1"));
+        assert_eq!("0.4.0", version(Some("0.4.0"), "HEAD"));
+        assert_eq!(UNRELEASED, version(None, "HEAD"));
+
+        let section = section(version(None, "HEAD"), "2025-06-01", "### Added\n- Compare");
+        assert_eq!("## [Unreleased]\n\n### Added\n- Compare\n", section);
+
+        let changelog = indoc! {"
+            # Changelog
+
+            ## [Unreleased]
+
+            ### Added
+            - Stale notes
+
+            ## [0.3.1] - 2025-05-01
+        "};
+        assert_eq!(
+            indoc! {"
+                # Changelog
+
+                ## [Unreleased]
+
+                ### Added
+                - Compare
+
+                ## [0.3.1] - 2025-05-01
+            "},
+            prepend(changelog, &section)
+        );
+        assert_eq!(
+            "# Changelog\n\n## [Unreleased]\n\n### Added\n- Compare\n",
+            prepend(
+                "# Changelog\n\n## [Unreleased]\n\n- Stale notes\n",
+                &section
+            )
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use log::{debug, info};
+
+use crate::git::Repository;
+
+/// Commits fetched at a time while looking for the merge base in a shallow clone
+const DEEPEN_BY: u32 = 100;
+/// Rounds of deepening before fetching the whole history
+const DEEPEN_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ci {
+    GitHubActions,
+    GitLab,
+    Buildkite,
+}
+
+impl Ci {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::GitHubActions => "GitHub Actions",
+            Self::GitLab => "GitLab CI",
+            Self::Buildkite => "Buildkite",
+        }
+    }
+}
+
+/// What a CI job knows about the pull request it is building
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Context {
+    pub ci: Ci,
+    /// The revision the pull request is compared against, like `origin/main`
+    pub base: Option<String>,
+    /// What to fetch from `origin` when `base` is missing from a shallow clone
+    refspec: Option<String>,
+    /// Markdown file shown on the job's summary page
+    pub summary: Option<PathBuf>,
+}
+
+impl Context {
+    /// Detect the CI service from the environment of the current process
+    pub fn detect() -> Option<Self> {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    /// Detect the CI service from the variables `var` looks up. Empty variables are unset,
+    /// CI services define most of them for every job and leave them blank outside of PRs.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let var = |name: &str| var(name).filter(|v| !v.trim().is_empty());
+        let set = |name: &str| var(name).is_some_and(|v| v != "false");
+
+        let (ci, base) = if set("GITHUB_ACTIONS") {
+            (Ci::GitHubActions, var("GITHUB_BASE_REF").map(Base::Branch))
+        } else if set("GITLAB_CI") {
+            // The diff base is the merge base GitLab itself shows for the merge request
+            let base = var("CI_MERGE_REQUEST_DIFF_BASE_SHA")
+                .map(Base::Commit)
+                .or_else(|| var("CI_MERGE_REQUEST_TARGET_BRANCH_NAME").map(Base::Branch));
+            (Ci::GitLab, base)
+        } else if set("BUILDKITE") {
+            (
+                Ci::Buildkite,
+                var("BUILDKITE_PULL_REQUEST_BASE_BRANCH").map(Base::Branch),
+            )
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            ci,
+            base: base.as_ref().map(Base::revision),
+            refspec: base.as_ref().map(Base::refspec),
+            summary: var("GITHUB_STEP_SUMMARY").map(PathBuf::from),
+        })
+    }
+
+    /// Fetch enough history from `origin` for `base` and the merge base of `base` and
+    /// `head` to exist, deepening a shallow clone a little at a time.
+    ///
+    /// This runs the `git` binary, which unlike libgit2 picks up the credentials CI
+    /// checkouts configure, like the `http.extraheader` set by `actions/checkout`.
+    pub fn fetch_history(&self, head: &str) -> Result<()> {
+        let (Some(base), Some(refspec)) = (&self.base, &self.refspec) else {
+            return Ok(());
+        };
+
+        if Repository::open()?.resolve(base).is_err() {
+            info!("Fetching `{base}` from origin");
+            fetch(&[&format!("--depth={DEEPEN_BY}"), "origin", refspec])?;
+        }
+
+        for _ in 0..DEEPEN_ATTEMPTS {
+            // Reopen so libgit2 sees the objects and shallow boundary of the last fetch
+            let repo = Repository::open()?;
+            if repo.merge_base(base, head).is_ok() || !repo.inner().is_shallow() {
+                return Ok(());
+            }
+            info!("No merge base for `{base}` and `{head}` yet, deepening by {DEEPEN_BY} commits");
+            fetch(&[&format!("--deepen={DEEPEN_BY}"), "origin", refspec])?;
+        }
+
+        if Repository::open()?.inner().is_shallow() {
+            info!("Fetching the full history of origin");
+            fetch(&["--unshallow", "origin", refspec])?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a pull request is based, as reported by the CI service
+enum Base {
+    Branch(String),
+    Commit(String),
+}
+
+impl Base {
+    fn revision(&self) -> String {
+        match self {
+            Self::Branch(branch) => format!("origin/{branch}"),
+            Self::Commit(sha) => sha.clone(),
+        }
+    }
+
+    fn refspec(&self) -> String {
+        match self {
+            Self::Branch(branch) => format!("+refs/heads/{branch}:refs/remotes/origin/{branch}"),
+            Self::Commit(sha) => sha.clone(),
+        }
+    }
+}
+
+fn fetch(args: &[&str]) -> Result<()> {
+    debug!("Running `git fetch --no-tags {}`", args.join(" "));
+    let output = Command::new("git")
+        .args(["fetch", "--no-tags", "--quiet"])
+        .args(args)
+        .output()
+        .context("Unable to run `git fetch`, is git installed?")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`git fetch` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Append `text` to the job summary at `path`, fenced as `language` when it is not markdown
+pub fn write_summary(path: &Path, text: &str, language: Option<&str>) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Unable to open the job summary `{}`", path.display()))?;
+
+    let text = text.trim_end();
+    match language {
+        Some(language) => writeln!(file, "```{language}\n{text}\n```\n")?,
+        None => writeln!(file, "{text}\n")?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use pretty_assertions::assert_eq;
+
+    fn detect(vars: &[(&str, &str)]) -> Option<Context> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        Context::from_env(|name| vars.get(name).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn test_from_env() {
+        assert_eq!(None, detect(&[("CI", "true")]));
+
+        let github = detect(&[
+            ("GITHUB_ACTIONS", "true"),
+            ("GITHUB_BASE_REF", "main"),
+            ("GITHUB_HEAD_REF", "feature"),
+            ("GITHUB_STEP_SUMMARY", "/tmp/summary.md"),
+        ])
+        .unwrap();
+        assert_eq!(Ci::GitHubActions, github.ci);
+        assert_eq!(Some(String::from("origin/main")), github.base);
+        assert_eq!(
+            Some(String::from("+refs/heads/main:refs/remotes/origin/main")),
+            github.refspec
+        );
+        assert_eq!(Some(PathBuf::from("/tmp/summary.md")), github.summary);
+
+        // Push builds have no base
+        let push = detect(&[("GITHUB_ACTIONS", "true"), ("GITHUB_BASE_REF", "")]).unwrap();
+        assert_eq!(None, push.base);
+
+        let gitlab = detect(&[
+            ("GITLAB_CI", "true"),
+            ("CI_MERGE_REQUEST_TARGET_BRANCH_NAME", "develop"),
+            ("CI_MERGE_REQUEST_DIFF_BASE_SHA", "3f2a9c1"),
+        ])
+        .unwrap();
+        assert_eq!(Ci::GitLab, gitlab.ci);
+        assert_eq!(Some(String::from("3f2a9c1")), gitlab.base);
+
+        let buildkite = detect(&[
+            ("BUILDKITE", "true"),
+            ("BUILDKITE_PULL_REQUEST_BASE_BRANCH", "release"),
+        ])
+        .unwrap();
+        assert_eq!(Ci::Buildkite, buildkite.ci);
+        assert_eq!(Some(String::from("origin/release")), buildkite.base);
+        assert_eq!(None, buildkite.summary);
+    }
+}
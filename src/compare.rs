@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::providers::{Completion, Usage};
+
+/// Separator between the columns of the side by side layout
+const GUTTER: &str = " │ ";
+/// Narrower columns are unreadable, the results are listed one after another instead
+const MIN_COLUMN_WIDTH: usize = 30;
+
+/// What one profile made of the prompt
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    pub profile: String,
+    pub provider: String,
+    pub model: String,
+    /// Time until the full response arrived, including retries
+    pub latency_ms: u64,
+    pub usage: Option<Usage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Comparison {
+    pub fn new(
+        profile: &str,
+        provider: &str,
+        model: &str,
+        latency: Duration,
+        result: Result<Completion>,
+    ) -> Self {
+        let (text, usage, error) = match result {
+            Ok(completion) => (Some(completion.text), completion.usage, None),
+            Err(e) => (None, None, Some(format!("{e:#}"))),
+        };
+
+        Self {
+            profile: profile.to_string(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            latency_ms: latency.as_millis() as u64,
+            usage,
+            text,
+            error,
+        }
+    }
+
+    /// Heading lines: the profile, where it sent the prompt, and what that cost
+    fn header(&self) -> Vec<String> {
+        let usage = match self.usage {
+            Some(usage) => format!(
+                "{} in / {} out tokens",
+                usage.input_tokens, usage.output_tokens
+            ),
+            None => String::from("usage not reported"),
+        };
+        vec![
+            self.profile.clone(),
+            format!("{} {}", self.provider, self.model),
+            format!("{:.1}s, {usage}", self.latency_ms as f64 / 1000.0),
+        ]
+    }
+
+    fn body(&self) -> String {
+        match (&self.text, &self.error) {
+            (Some(text), _) => text.trim().to_string(),
+            (None, Some(error)) => format!("Error: {error}"),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+pub fn to_json(comparisons: &[Comparison]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(comparisons)?)
+}
+
+/// Lay the results out in columns filling `width`, or one after another when they do not fit
+pub fn to_text(comparisons: &[Comparison], width: usize) -> String {
+    let count = comparisons.len().max(1);
+    let column = width.saturating_sub(GUTTER.chars().count() * (count - 1)) / count;
+    if column < MIN_COLUMN_WIDTH {
+        return stacked(comparisons, width);
+    }
+
+    let columns: Vec<Vec<String>> = comparisons
+        .iter()
+        .map(|comparison| {
+            let mut lines: Vec<String> = comparison
+                .header()
+                .iter()
+                .flat_map(|line| wrap(line, column))
+                .collect();
+            lines.push("─".repeat(column));
+            lines.extend(wrap(&comparison.body(), column));
+            lines
+        })
+        .collect();
+
+    let height = columns.iter().map(Vec::len).max().unwrap_or_default();
+    let mut text = String::new();
+    for row in 0..height {
+        let cells: Vec<String> = columns
+            .iter()
+            .map(|lines| {
+                let cell = lines.get(row).map(String::as_str).unwrap_or_default();
+                format!("{cell:<column$}")
+            })
+            .collect();
+        text.push_str(cells.join(GUTTER).trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+fn stacked(comparisons: &[Comparison], width: usize) -> String {
+    comparisons
+        .iter()
+        .map(|comparison| {
+            format!(
+                "{}\n{}\n{}\n",
+                comparison.header().join("\n"),
+                "─".repeat(width),
+                comparison.body()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Break `text` into lines of at most `width` characters at spaces, splitting words which
+/// are longer than a line
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let mut word = word.to_string();
+            while word.chars().count() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let rest = word.split_off(word.char_indices().nth(width).unwrap().0);
+                lines.push(word);
+                word = rest;
+            }
+
+            let length = line.chars().count();
+            if length > 0 && length + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            } else if length > 0 {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use crate::providers::FinishReason;
+
+    fn comparisons() -> Vec<Comparison> {
+        vec![
+            Comparison::new(
+                "claude",
+                "anthropic",
+                "claude-sonnet-4-5",
+                Duration::from_millis(2340),
+                Ok(Completion {
+                    text: String::from(
+                        "Adds side by side comparisons of profiles\n\n- Runs them concurrently",
+                    ),
+                    finish_reason: FinishReason::Stop,
+                    usage: Some(Usage {
+                        input_tokens: 1200,
+                        output_tokens: 85,
+                    }),
+                }),
+            ),
+            Comparison::new(
+                "ollama",
+                "ollama",
+                "codegemma:7b",
+                Duration::from_millis(800),
+                Err(anyhow!("Connection refused")),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_to_text() {
+        assert_eq!(
+            indoc! {"
+                claude                             │ ollama
+                anthropic claude-sonnet-4-5        │ ollama codegemma:7b
+                2.3s, 1200 in / 85 out tokens      │ 0.8s, usage not reported
+                ────────────────────────────────── │ ──────────────────────────────────
+                Adds side by side comparisons of   │ Error: Connection refused
+                profiles                           │
+                                                   │
+                - Runs them concurrently           │
+            "},
+            to_text(&comparisons(), 71)
+        );
+    }
+
+    #[test]
+    fn test_to_text_stacks_narrow_columns() {
+        assert_eq!(
+            indoc! {"
+                ollama
+                ollama codegemma:7b
+                0.8s, usage not reported
+                ──────────
+                Error: Connection refused
+            "},
+            to_text(&comparisons()[1..], 10)
+        );
+    }
+
+    #[test]
+    fn test_wrap_splits_long_words() {
+        assert_eq!(
+            vec!["see", "https://exa", "mple.com/a", "b"],
+            wrap("see https://example.com/a b", 11)
+        );
+    }
+}
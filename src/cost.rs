@@ -0,0 +1,114 @@
+use crate::{providers::Usage, settings::PriceSettings};
+
+/// Tokens used by a profile during a run, and its price when one is configured
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spend {
+    pub profile: String,
+    pub usage: Usage,
+    pub price: Option<PriceSettings>,
+}
+
+impl Spend {
+    pub fn cost(&self) -> Option<f64> {
+        self.price.map(|price| price.cost(&self.usage))
+    }
+}
+
+/// One line per profile with its tokens and estimated cost, and a total when more than
+/// one profile was used
+pub fn report(spends: &[Spend]) -> String {
+    if spends.is_empty() {
+        return String::from("The provider did not report token usage\n");
+    }
+
+    let mut report: String = spends
+        .iter()
+        .map(|spend| {
+            format!(
+                "{}: {}, {}\n",
+                spend.profile,
+                tokens(&spend.usage),
+                spend
+                    .cost()
+                    .map_or(String::from("no price configured"), dollars)
+            )
+        })
+        .collect();
+
+    if spends.len() > 1 {
+        let usage = spends
+            .iter()
+            .fold(None, |total, spend| {
+                Usage::combine(total, Some(spend.usage))
+            })
+            .unwrap_or_default();
+        let cost: f64 = spends.iter().filter_map(Spend::cost).sum();
+        let priced = spends.iter().all(|spend| spend.price.is_some());
+        report.push_str(&format!(
+            "Total: {}, {}{}\n",
+            tokens(&usage),
+            dollars(cost),
+            if priced {
+                ""
+            } else {
+                " for the priced profiles"
+            }
+        ));
+    }
+    report
+}
+
+fn tokens(usage: &Usage) -> String {
+    format!(
+        "{} input + {} output tokens",
+        usage.input_tokens, usage.output_tokens
+    )
+}
+
+fn dollars(cost: f64) -> String {
+    format!("~${cost:.4}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_report() {
+        let claude = Spend {
+            profile: String::from("claude"),
+            usage: Usage {
+                input_tokens: 12_000,
+                output_tokens: 400,
+            },
+            price: Some(PriceSettings {
+                input: 3.0,
+                output: 15.0,
+            }),
+        };
+        let ollama = Spend {
+            profile: String::from("ollama"),
+            usage: Usage {
+                input_tokens: 3_000,
+                output_tokens: 250,
+            },
+            price: None,
+        };
+
+        assert_eq!(
+            "claude: 12000 input + 400 output tokens, ~$0.0420\n",
+            report(std::slice::from_ref(&claude))
+        );
+        assert_eq!(
+            indoc! {"
+                claude: 12000 input + 400 output tokens, ~$0.0420
+                ollama: 3000 input + 250 output tokens, no price configured
+                Total: 15000 input + 650 output tokens, ~$0.0420 for the priced profiles
+            "},
+            report(&[claude, ollama])
+        );
+    }
+}
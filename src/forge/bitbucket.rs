@@ -0,0 +1,228 @@
+use anyhow::{Result, anyhow};
+use log::debug;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderValue};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::forge::{self, Forge, NewPullRequest, PullRequest, Remote};
+
+/// Client for the pull request endpoints of the Bitbucket Server and Data Center REST API
+/// (`/rest/api/1.0`)
+pub struct Bitbucket {
+    api_url: String,
+    project: String,
+    slug: String,
+    client: reqwest::blocking::Client,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    values: Vec<Value>,
+}
+
+/// Convert a pull request resource, whose URL is under `links.self`
+fn from_resource(value: &Value) -> Result<PullRequest> {
+    let number = value
+        .get("id")
+        .and_then(|id| id.as_u64())
+        .ok_or(anyhow!("The Bitbucket pull request has no id"))?;
+    let html_url = value
+        .pointer("/links/self/0/href")
+        .and_then(|href| href.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    Ok(PullRequest { number, html_url })
+}
+
+impl Bitbucket {
+    pub fn new(api_url: &str, token: &SecretString, remote: &Remote) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token.expose_secret()))?,
+        );
+
+        // HTTP remotes are `/scm/PROJECT/repo.git`, possibly below a context path, while
+        // SSH remotes are `/project/repo.git`. Personal repositories use `~user` as the key.
+        let project = remote.owner.rsplit('/').next().unwrap_or(&remote.owner);
+
+        Ok(Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            project: project.to_string(),
+            slug: remote.name.clone(),
+            client: forge::client(headers)?,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/projects/{}/repos/{}/pull-requests{path}",
+            self.api_url, self.project, self.slug
+        )
+    }
+
+    fn reference(&self, branch: &str) -> Value {
+        json!({
+            "id": format!("refs/heads/{branch}"),
+            "repository": {
+                "slug": self.slug,
+                "project": { "key": self.project }
+            }
+        })
+    }
+}
+
+impl Forge for Bitbucket {
+    fn name(&self) -> &'static str {
+        "Bitbucket"
+    }
+
+    fn find_pull_request(&self, branch: &str) -> Result<Option<PullRequest>> {
+        debug!("Looking up the open pull request for {branch}");
+
+        let at = format!("refs/heads/{branch}");
+        let page: Page = forge::send(
+            self.name(),
+            self.client.get(self.url("")).query(&[
+                ("at", at.as_str()),
+                ("direction", "OUTGOING"),
+                ("state", "OPEN"),
+            ]),
+        )?;
+        page.values.first().map(from_resource).transpose()
+    }
+
+    fn create_pull_request(&self, pull_request: &NewPullRequest) -> Result<PullRequest> {
+        let mut body = json!({
+            "title": pull_request.title,
+            "description": pull_request.body,
+            "fromRef": self.reference(pull_request.head),
+            "toRef": self.reference(pull_request.base),
+        });
+        // Only sent when needed, servers before 8.18 do not know about drafts
+        if pull_request.draft {
+            body["draft"] = json!(true);
+        }
+
+        let created: Value = forge::send(self.name(), self.client.post(self.url("")).json(&body))?;
+        from_resource(&created)
+    }
+
+    fn update_pull_request(&self, number: u64, body: &str) -> Result<PullRequest> {
+        let url = self.url(&format!("/{number}"));
+        let existing: Value = forge::send(self.name(), self.client.get(&url))?;
+
+        // Updates must carry the current version, and fields left out are cleared, so
+        // send back the title, reviewers and target as they are
+        let updated: Value = forge::send(
+            self.name(),
+            self.client.put(&url).json(&json!({
+                "version": existing["version"],
+                "title": existing["title"],
+                "description": body,
+                "reviewers": existing["reviewers"],
+                "toRef": existing["toRef"],
+            })),
+        )?;
+        from_resource(&updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_pull_requests() {
+        let mut server = mockito::Server::new();
+        let resource = |version: u64, description: &str| {
+            json!({
+                "id": 42,
+                "version": version,
+                "title": "Add Bitbucket",
+                "description": description,
+                "reviewers": [{ "user": { "name": "jdoe" } }],
+                "toRef": { "id": "refs/heads/main" },
+                "links": { "self": [{ "href": "https://bitbucket.example.com/projects/PROJ/repos/api/pull-requests/42" }] }
+            })
+        };
+        let path = "/projects/PROJ/repos/api/pull-requests";
+
+        let find = server
+            .mock("GET", path)
+            .match_header("authorization", "Bearer token")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("at".into(), "refs/heads/feature".into()),
+                mockito::Matcher::UrlEncoded("direction".into(), "OUTGOING".into()),
+                mockito::Matcher::UrlEncoded("state".into(), "OPEN".into()),
+            ]))
+            .with_body(json!({ "values": [resource(3, "Old")] }).to_string())
+            .create();
+        let create = server
+            .mock("POST", path)
+            .match_body(mockito::Matcher::Json(json!({
+                "title": "Add Bitbucket",
+                "description": "Publishes pull requests",
+                "fromRef": {
+                    "id": "refs/heads/feature",
+                    "repository": { "slug": "api", "project": { "key": "PROJ" } }
+                },
+                "toRef": {
+                    "id": "refs/heads/main",
+                    "repository": { "slug": "api", "project": { "key": "PROJ" } }
+                }
+            })))
+            .with_status(201)
+            .with_body(resource(0, "Publishes pull requests").to_string())
+            .create();
+        server
+            .mock("GET", format!("{path}/42").as_str())
+            .with_body(resource(3, "Old").to_string())
+            .create();
+        let update = server
+            .mock("PUT", format!("{path}/42").as_str())
+            .match_body(mockito::Matcher::Json(json!({
+                "version": 3,
+                "title": "Add Bitbucket",
+                "description": "Updated",
+                "reviewers": [{ "user": { "name": "jdoe" } }],
+                "toRef": { "id": "refs/heads/main" }
+            })))
+            .with_body(resource(4, "Updated").to_string())
+            .create();
+
+        let remote = Remote::parse("https://bitbucket.example.com/scm/PROJ/api.git").unwrap();
+        let bitbucket = Bitbucket::new(&server.url(), &"token".into(), &remote).unwrap();
+
+        let existing = bitbucket.find_pull_request("feature").unwrap().unwrap();
+        assert_eq!(42, existing.number);
+        assert_eq!(
+            "https://bitbucket.example.com/projects/PROJ/repos/api/pull-requests/42",
+            existing.html_url
+        );
+        assert_eq!(
+            existing,
+            bitbucket
+                .create_pull_request(&NewPullRequest {
+                    title: "Add Bitbucket",
+                    body: "Publishes pull requests",
+                    head: "feature",
+                    base: "main",
+                    draft: false,
+                })
+                .unwrap()
+        );
+        assert_eq!(
+            existing,
+            bitbucket.update_pull_request(42, "Updated").unwrap()
+        );
+
+        find.assert();
+        create.assert();
+        update.assert();
+    }
+}
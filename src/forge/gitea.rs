@@ -0,0 +1,193 @@
+use anyhow::Result;
+use log::debug;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderValue};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::forge::{self, Forge, NewPullRequest, PullRequest, Remote};
+
+/// Open pull requests asked for per page when looking one up by branch. Servers cap this
+/// at their `MAX_RESPONSE_ITEMS`, so pages may come back shorter.
+const PAGE_SIZE: usize = 50;
+
+/// Client for the pull request endpoints of the Gitea and Forgejo REST API (`/api/v1`)
+pub struct Gitea {
+    api_url: String,
+    owner: String,
+    name: String,
+    client: reqwest::blocking::Client,
+}
+
+#[derive(Deserialize)]
+struct Pull {
+    number: u64,
+    html_url: String,
+    head: Branch,
+}
+
+#[derive(Deserialize)]
+struct Branch {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+impl Gitea {
+    pub fn new(api_url: &str, token: &SecretString, remote: &Remote) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("token {}", token.expose_secret()))?,
+        );
+
+        Ok(Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            owner: remote.owner.clone(),
+            name: remote.name.clone(),
+            client: forge::client(headers)?,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/repos/{}/{}/pulls{path}",
+            self.api_url, self.owner, self.name
+        )
+    }
+}
+
+impl Forge for Gitea {
+    fn name(&self) -> &'static str {
+        "Gitea"
+    }
+
+    fn find_pull_request(&self, branch: &str) -> Result<Option<PullRequest>> {
+        debug!("Looking up the open pull request for {branch}");
+
+        // The API cannot filter by head branch, so page through the open pull requests
+        // until one comes back empty
+        for page in 1.. {
+            let pulls: Vec<Pull> = forge::send(
+                self.name(),
+                self.client.get(self.url("")).query(&[
+                    ("state", String::from("open")),
+                    ("page", page.to_string()),
+                    ("limit", PAGE_SIZE.to_string()),
+                ]),
+            )?;
+            if pulls.is_empty() {
+                break;
+            }
+
+            if let Some(pull) = pulls.into_iter().find(|p| p.head.name == branch) {
+                return Ok(Some(PullRequest {
+                    number: pull.number,
+                    html_url: pull.html_url,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn create_pull_request(&self, pull_request: &NewPullRequest) -> Result<PullRequest> {
+        // Work in progress is marked by the title rather than a field
+        let title = if pull_request.draft {
+            format!("WIP: {}", pull_request.title)
+        } else {
+            pull_request.title.to_string()
+        };
+
+        forge::send(
+            self.name(),
+            self.client.post(self.url("")).json(&json!({
+                "title": title,
+                "body": pull_request.body,
+                "head": pull_request.head,
+                "base": pull_request.base,
+            })),
+        )
+    }
+
+    fn update_pull_request(&self, number: u64, body: &str) -> Result<PullRequest> {
+        forge::send(
+            self.name(),
+            self.client
+                .patch(self.url(&format!("/{number}")))
+                .json(&json!({ "body": body })),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_pull_requests() {
+        let mut server = mockito::Server::new();
+        let pull = |number: u64, branch: &str| {
+            json!({
+                "number": number,
+                "html_url": format!("https://codeberg.org/team/web/pulls/{number}"),
+                "head": { "ref": branch }
+            })
+        };
+        // A server whose `MAX_RESPONSE_ITEMS` is below `PAGE_SIZE` returns short pages
+        let first_page: Vec<_> = (1..=30).map(|n| pull(n, "other")).collect();
+        server
+            .mock("GET", "/repos/team/web/pulls")
+            .match_header("authorization", "token token")
+            .match_query(mockito::Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(serde_json::to_string(&first_page).unwrap())
+            .create();
+        server
+            .mock("GET", "/repos/team/web/pulls")
+            .match_query(mockito::Matcher::UrlEncoded("page".into(), "2".into()))
+            .with_body(serde_json::to_string(&[pull(60, "feature")]).unwrap())
+            .create();
+        server
+            .mock("GET", "/repos/team/web/pulls")
+            .match_query(mockito::Matcher::UrlEncoded("page".into(), "3".into()))
+            .with_body("[]")
+            .create();
+        let create = server
+            .mock("POST", "/repos/team/web/pulls")
+            .match_body(mockito::Matcher::Json(json!({
+                "title": "WIP: Add forges",
+                "body": "Publishes pull requests",
+                "head": "feature",
+                "base": "main"
+            })))
+            .with_status(201)
+            .with_body(pull(61, "feature").to_string())
+            .create();
+        let update = server
+            .mock("PATCH", "/repos/team/web/pulls/60")
+            .match_body(mockito::Matcher::Json(json!({"body": "Updated"})))
+            .with_body(pull(60, "feature").to_string())
+            .create();
+
+        let remote = Remote::parse("https://codeberg.org/team/web.git").unwrap();
+        let gitea = Gitea::new(&server.url(), &"token".into(), &remote).unwrap();
+
+        let existing = gitea.find_pull_request("feature").unwrap().unwrap();
+        assert_eq!(60, existing.number);
+        assert_eq!(None, gitea.find_pull_request("missing").unwrap());
+        assert_eq!(existing, gitea.update_pull_request(60, "Updated").unwrap());
+        let created = gitea
+            .create_pull_request(&NewPullRequest {
+                title: "Add forges",
+                body: "Publishes pull requests",
+                head: "feature",
+                base: "main",
+                draft: true,
+            })
+            .unwrap();
+        assert_eq!(61, created.number);
+
+        create.assert();
+        update.assert();
+    }
+}
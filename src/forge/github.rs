@@ -0,0 +1,218 @@
+use anyhow::Result;
+use log::debug;
+use reqwest::header::{ACCEPT, AUTHORIZATION, HeaderMap, HeaderValue};
+use secrecy::{ExposeSecret, SecretString};
+use serde_json::json;
+
+use crate::forge::{self, Forge, NewPullRequest, PullRequest, Remote};
+
+const API_VERSION: &str = "2022-11-28";
+
+/// Client for the pull request endpoints of the GitHub REST API
+pub struct GitHub {
+    api_url: String,
+    owner: String,
+    name: String,
+    client: reqwest::blocking::Client,
+}
+
+impl GitHub {
+    pub fn new(api_url: &str, token: &SecretString, remote: &Remote) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token.expose_secret()))?,
+        );
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/vnd.github+json"),
+        );
+        headers.insert(
+            "x-github-api-version",
+            HeaderValue::from_static(API_VERSION),
+        );
+
+        Ok(Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            owner: remote.owner.clone(),
+            name: remote.name.clone(),
+            client: forge::client(headers)?,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/repos/{}/{}/pulls{path}",
+            self.api_url, self.owner, self.name
+        )
+    }
+}
+
+impl Forge for GitHub {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn find_pull_request(&self, branch: &str) -> Result<Option<PullRequest>> {
+        let head = format!("{}:{branch}", self.owner);
+        debug!("Looking up the open pull request for {head}");
+
+        let pulls: Vec<PullRequest> = forge::send(
+            self.name(),
+            self.client
+                .get(self.url(""))
+                .query(&[("head", head.as_str()), ("state", "open")]),
+        )?;
+        Ok(pulls.into_iter().next())
+    }
+
+    fn create_pull_request(&self, pull_request: &NewPullRequest) -> Result<PullRequest> {
+        forge::send(
+            self.name(),
+            self.client.post(self.url("")).json(&json!({
+                "title": pull_request.title,
+                "body": pull_request.body,
+                "head": pull_request.head,
+                "base": pull_request.base,
+                "draft": pull_request.draft,
+            })),
+        )
+    }
+
+    fn update_pull_request(&self, number: u64, body: &str) -> Result<PullRequest> {
+        forge::send(
+            self.name(),
+            self.client
+                .patch(self.url(&format!("/{number}")))
+                .json(&json!({ "body": body })),
+        )
+    }
+}
+
+/// The token the `gh` CLI is logged in to `host` with. `gh` keeps tokens in the system
+/// keyring, so ask it rather than reading its config
+pub fn gh_token(host: &str) -> Option<SecretString> {
+    let output = std::process::Command::new("gh")
+        .args(["auth", "token", "--hostname", host])
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8(output.stdout)
+            .ok()
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .map(SecretString::from),
+        Ok(_) => {
+            debug!("`gh` is not logged in to {host}");
+            None
+        }
+        Err(e) => {
+            debug!("Unable to run `gh auth token`: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    fn client(server: &mockito::Server) -> GitHub {
+        let remote = Remote::parse("git@github.com:octo/prai.git").unwrap();
+        GitHub::new(&server.url(), &"token".into(), &remote).unwrap()
+    }
+
+    #[test]
+    fn test_create_and_update_pull_request() {
+        let mut server = mockito::Server::new();
+        let create = server
+            .mock("POST", "/repos/octo/prai/pulls")
+            .match_header("authorization", "Bearer token")
+            .match_body(mockito::Matcher::Json(json!({
+                "title": "Add forge support",
+                "body": "Opens pull requests",
+                "head": "feature",
+                "base": "main",
+                "draft": true
+            })))
+            .with_status(201)
+            .with_body(r#"{"number": 7, "html_url": "https://github.com/octo/prai/pull/7"}"#)
+            .create();
+        let update = server
+            .mock("PATCH", "/repos/octo/prai/pulls/7")
+            .match_body(mockito::Matcher::Json(json!({"body": "Updated"})))
+            .with_body(r#"{"number": 7, "html_url": "https://github.com/octo/prai/pull/7"}"#)
+            .create();
+
+        let github = client(&server);
+        let pull_request = github
+            .create_pull_request(&NewPullRequest {
+                title: "Add forge support",
+                body: "Opens pull requests",
+                head: "feature",
+                base: "main",
+                draft: true,
+            })
+            .unwrap();
+        assert_eq!(7, pull_request.number);
+        assert_eq!(
+            pull_request,
+            github.update_pull_request(7, "Updated").unwrap()
+        );
+
+        create.assert();
+        update.assert();
+    }
+
+    #[test]
+    fn test_find_pull_request() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/repos/octo/prai/pulls")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("head".into(), "octo:feature".into()),
+                mockito::Matcher::UrlEncoded("state".into(), "open".into()),
+            ]))
+            .with_body(r#"[{"number": 3, "html_url": "https://github.com/octo/prai/pull/3"}]"#)
+            .create();
+        server
+            .mock("GET", "/repos/octo/prai/pulls")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "head".into(),
+                "octo:missing".into(),
+            ))
+            .with_body("[]")
+            .create();
+        server
+            .mock("POST", "/repos/octo/prai/pulls")
+            .with_status(422)
+            .with_body(r#"{"message": "Validation Failed", "errors": [{"message": "A pull request already exists for octo:feature."}]}"#)
+            .create();
+
+        let github = client(&server);
+        assert_eq!(
+            Some(3),
+            github
+                .find_pull_request("feature")
+                .unwrap()
+                .map(|pr| pr.number)
+        );
+        assert_eq!(None, github.find_pull_request("missing").unwrap());
+
+        let error = github
+            .create_pull_request(&NewPullRequest {
+                title: "x",
+                body: "x",
+                head: "feature",
+                base: "main",
+                draft: false,
+            })
+            .unwrap_err();
+        assert_eq!(
+            "GitHub API request failed with status 422 Unprocessable Entity: Validation Failed: A pull request already exists for octo:feature.",
+            error.to_string()
+        );
+    }
+}
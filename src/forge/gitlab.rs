@@ -0,0 +1,160 @@
+use anyhow::Result;
+use log::debug;
+use reqwest::header::{HeaderMap, HeaderValue};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::forge::{self, Forge, NewPullRequest, PullRequest, Remote};
+
+/// Client for the merge request endpoints of the GitLab REST API (`/api/v4`)
+pub struct GitLab {
+    api_url: String,
+    /// URL encoded `namespace/project` path, which GitLab accepts in place of the ID
+    project: String,
+    client: reqwest::blocking::Client,
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    iid: u64,
+    web_url: String,
+}
+
+impl From<MergeRequest> for PullRequest {
+    fn from(merge_request: MergeRequest) -> Self {
+        Self {
+            number: merge_request.iid,
+            html_url: merge_request.web_url,
+        }
+    }
+}
+
+impl GitLab {
+    pub fn new(api_url: &str, token: &SecretString, remote: &Remote) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "private-token",
+            HeaderValue::from_str(token.expose_secret())?,
+        );
+
+        Ok(Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            project: remote.path().replace('/', "%2F"),
+            client: forge::client(headers)?,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/projects/{}/merge_requests{path}",
+            self.api_url, self.project
+        )
+    }
+}
+
+impl Forge for GitLab {
+    fn name(&self) -> &'static str {
+        "GitLab"
+    }
+
+    fn find_pull_request(&self, branch: &str) -> Result<Option<PullRequest>> {
+        debug!("Looking up the open merge request for {branch}");
+
+        let merge_requests: Vec<MergeRequest> = forge::send(
+            self.name(),
+            self.client
+                .get(self.url(""))
+                .query(&[("source_branch", branch), ("state", "opened")]),
+        )?;
+        Ok(merge_requests.into_iter().next().map(PullRequest::from))
+    }
+
+    fn create_pull_request(&self, pull_request: &NewPullRequest) -> Result<PullRequest> {
+        // Drafts are marked by the title rather than a field
+        let title = if pull_request.draft {
+            format!("Draft: {}", pull_request.title)
+        } else {
+            pull_request.title.to_string()
+        };
+
+        forge::send::<MergeRequest>(
+            self.name(),
+            self.client.post(self.url("")).json(&json!({
+                "title": title,
+                "description": pull_request.body,
+                "source_branch": pull_request.head,
+                "target_branch": pull_request.base,
+            })),
+        )
+        .map(PullRequest::from)
+    }
+
+    fn update_pull_request(&self, number: u64, body: &str) -> Result<PullRequest> {
+        forge::send::<MergeRequest>(
+            self.name(),
+            self.client
+                .put(self.url(&format!("/{number}")))
+                .json(&json!({ "description": body })),
+        )
+        .map(PullRequest::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_merge_requests() {
+        let mut server = mockito::Server::new();
+        let find = server
+            .mock("GET", "/projects/group%2Fsub%2Fapi/merge_requests")
+            .match_header("private-token", "token")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("source_branch".into(), "feature".into()),
+                mockito::Matcher::UrlEncoded("state".into(), "opened".into()),
+            ]))
+            .with_body(r#"[{"iid": 12, "web_url": "https://gitlab.example.com/group/sub/api/-/merge_requests/12"}]"#)
+            .create();
+        let create = server
+            .mock("POST", "/projects/group%2Fsub%2Fapi/merge_requests")
+            .match_body(mockito::Matcher::Json(json!({
+                "title": "Draft: Add forges",
+                "description": "Publishes merge requests",
+                "source_branch": "feature",
+                "target_branch": "main"
+            })))
+            .with_status(201)
+            .with_body(r#"{"iid": 13, "web_url": "https://gitlab.example.com/group/sub/api/-/merge_requests/13"}"#)
+            .create();
+        let update = server
+            .mock("PUT", "/projects/group%2Fsub%2Fapi/merge_requests/12")
+            .match_body(mockito::Matcher::Json(json!({"description": "Updated"})))
+            .with_body(r#"{"iid": 12, "web_url": "https://gitlab.example.com/group/sub/api/-/merge_requests/12"}"#)
+            .create();
+
+        let remote = Remote::parse("git@gitlab.example.com:group/sub/api.git").unwrap();
+        let gitlab = GitLab::new(&server.url(), &"token".into(), &remote).unwrap();
+
+        let existing = gitlab.find_pull_request("feature").unwrap().unwrap();
+        assert_eq!(12, existing.number);
+        assert_eq!(existing, gitlab.update_pull_request(12, "Updated").unwrap());
+        let created = gitlab
+            .create_pull_request(&NewPullRequest {
+                title: "Add forges",
+                body: "Publishes merge requests",
+                head: "feature",
+                base: "main",
+                draft: true,
+            })
+            .unwrap();
+        assert_eq!(13, created.number);
+
+        find.assert();
+        create.assert();
+        update.assert();
+    }
+}
@@ -0,0 +1,383 @@
+pub mod bitbucket;
+pub mod gitea;
+pub mod github;
+pub mod gitlab;
+
+use anyhow::{Result, anyhow};
+use log::debug;
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use secrecy::SecretString;
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::settings::{ForgeKind, ForgeSettings};
+
+/// A code host pull requests can be opened on
+pub trait Forge {
+    /// Name used in messages, like `GitLab`
+    fn name(&self) -> &'static str;
+
+    /// The open pull request from `branch`, if there is one
+    fn find_pull_request(&self, branch: &str) -> Result<Option<PullRequest>>;
+
+    fn create_pull_request(&self, pull_request: &NewPullRequest) -> Result<PullRequest>;
+
+    /// Replace the description of pull request `number`, leaving its title alone
+    fn update_pull_request(&self, number: u64, body: &str) -> Result<PullRequest>;
+}
+
+/// A pull request, or merge request on GitLab, as reported by the forge
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PullRequest {
+    /// Number shown in the forge's UI, the `iid` on GitLab
+    pub number: u64,
+    pub html_url: String,
+}
+
+/// What to open a pull request with
+#[derive(Debug, Clone)]
+pub struct NewPullRequest<'a> {
+    pub title: &'a str,
+    pub body: &'a str,
+    /// Branch holding the changes
+    pub head: &'a str,
+    /// Branch the changes are merged into
+    pub base: &'a str,
+    pub draft: bool,
+}
+
+/// The host and `owner/name` path of a remote repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    pub host: String,
+    pub owner: String,
+    pub name: String,
+}
+
+impl Remote {
+    /// Parse a remote URL in any of the forms git accepts: `https://host/owner/name.git`,
+    /// `ssh://git@host:22/owner/name.git` or the scp-like `git@host:owner/name.git`
+    pub fn parse(url: &str) -> Result<Self> {
+        let url = url.trim();
+        let (host, path) = match url.split_once("://") {
+            Some((_, rest)) => rest
+                .split_once('/')
+                .ok_or(anyhow!("Remote URL `{url}` has no repository path"))?,
+            None => url
+                .split_once(':')
+                .ok_or(anyhow!("Unrecognized remote URL `{url}`"))?,
+        };
+
+        // Drop credentials and ports, `https://token@host:8443` is just `host`
+        let host = host.rsplit('@').next().unwrap_or(host);
+        let host = host.split(':').next().unwrap_or(host);
+
+        let path = path.trim_matches('/').trim_end_matches(".git");
+        let (owner, name) = path
+            .rsplit_once('/')
+            .ok_or(anyhow!("Remote URL `{url}` is not of the form owner/name"))?;
+
+        Ok(Self {
+            host: host.to_string(),
+            owner: owner.to_string(),
+            name: name.to_string(),
+        })
+    }
+
+    /// The `owner/name` path of the repository
+    pub fn path(&self) -> String {
+        format!("{}/{}", self.owner, self.name)
+    }
+
+    /// The URL of `origin` in `repo`
+    pub fn origin(repo: &git2::Repository) -> Result<Self> {
+        let remote = repo
+            .find_remote("origin")
+            .map_err(|_| anyhow!("The repository has no `origin` remote"))?;
+        Self::parse(
+            remote
+                .url()
+                .ok_or(anyhow!("The `origin` URL is not valid UTF-8"))?,
+        )
+    }
+}
+
+/// Guess the forge behind `remote` for well known and conventionally named instances
+pub fn detect(remote: &Remote) -> Option<ForgeKind> {
+    // Bitbucket Server clones over HTTP from `/scm/PROJECT/repo.git`
+    let scm = remote.owner.starts_with("scm/") || remote.owner.contains("/scm/");
+
+    match remote.host.as_str() {
+        "github.com" => Some(ForgeKind::GitHub),
+        "gitlab.com" => Some(ForgeKind::GitLab),
+        "codeberg.org" | "gitea.com" => Some(ForgeKind::Gitea),
+        // Bitbucket Cloud has a different API
+        "bitbucket.org" => None,
+        host if host.contains("github") => Some(ForgeKind::GitHub),
+        host if host.contains("gitlab") => Some(ForgeKind::GitLab),
+        host if host.contains("gitea") || host.contains("forgejo") => Some(ForgeKind::Gitea),
+        host if host.contains("bitbucket") || scm => Some(ForgeKind::Bitbucket),
+        _ => None,
+    }
+}
+
+/// Root of the REST API a forge of `kind` serves on `host`
+pub fn api_url(kind: ForgeKind, host: &str) -> String {
+    match kind {
+        ForgeKind::GitHub if host == "github.com" => String::from("https://api.github.com"),
+        ForgeKind::GitHub => format!("https://{host}/api/v3"),
+        ForgeKind::GitLab => format!("https://{host}/api/v4"),
+        ForgeKind::Gitea => format!("https://{host}/api/v1"),
+        ForgeKind::Bitbucket => format!("https://{host}/rest/api/1.0"),
+    }
+}
+
+/// Connect to the forge hosting the `origin` remote of `repo`, using the `[[forge]]`
+/// entry for its host when there is one
+pub fn open(repo: &git2::Repository, settings: &[ForgeSettings]) -> Result<Box<dyn Forge>> {
+    let remote = Remote::origin(repo)?;
+    let configured = settings.iter().find(|f| f.host == remote.host);
+
+    let kind = configured
+        .and_then(|f| f.kind)
+        .or_else(|| detect(&remote))
+        .ok_or(anyhow!(
+            "Unable to tell which forge hosts `{}`. Add a `[[forge]]` entry with its `type`",
+            remote.host
+        ))?;
+    let api_url = configured
+        .and_then(|f| f.api_url.clone())
+        .unwrap_or_else(|| api_url(kind, &remote.host));
+    let token = token(
+        kind,
+        &remote.host,
+        configured.and_then(|f| f.token.as_ref()),
+    )?;
+    debug!("Publishing to {kind:?} at {api_url} for {}", remote.path());
+
+    Ok(match kind {
+        ForgeKind::GitHub => Box::new(github::GitHub::new(&api_url, &token, &remote)?),
+        ForgeKind::GitLab => Box::new(gitlab::GitLab::new(&api_url, &token, &remote)?),
+        ForgeKind::Gitea => Box::new(gitea::Gitea::new(&api_url, &token, &remote)?),
+        ForgeKind::Bitbucket => Box::new(bitbucket::Bitbucket::new(&api_url, &token, &remote)?),
+    })
+}
+
+/// Find a token for `host` in the forge's usual environment variables, then the
+/// configured one, then for GitHub whatever the `gh` CLI is logged in with
+fn token(kind: ForgeKind, host: &str, configured: Option<&SecretString>) -> Result<SecretString> {
+    let variables: &[&str] = match kind {
+        ForgeKind::GitHub => &["GH_TOKEN", "GITHUB_TOKEN"],
+        ForgeKind::GitLab => &["GITLAB_TOKEN"],
+        ForgeKind::Gitea => &["GITEA_TOKEN", "FORGEJO_TOKEN"],
+        ForgeKind::Bitbucket => &["BITBUCKET_TOKEN"],
+    };
+
+    if let Some(token) = variables
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|t| !t.trim().is_empty()))
+    {
+        return Ok(token.into());
+    }
+    if let Some(token) = configured {
+        return Ok(token.clone());
+    }
+    if kind == ForgeKind::GitHub
+        && let Some(token) = github::gh_token(host)
+    {
+        return Ok(token);
+    }
+
+    Err(anyhow!(
+        "No token found for {host}. Set `{}` or `token` in its `[[forge]]` entry",
+        variables.join("` or `")
+    ))
+}
+
+/// A blocking client sending `headers` with every request
+pub(crate) fn client(mut headers: HeaderMap) -> Result<reqwest::blocking::Client> {
+    headers.insert(
+        USER_AGENT,
+        HeaderValue::from_static(concat!("prai/", env!("CARGO_PKG_VERSION"))),
+    );
+    Ok(reqwest::blocking::Client::builder()
+        .default_headers(headers)
+        .build()?)
+}
+
+/// Send an API request to `forge` and deserialize the response
+pub(crate) fn send<T: DeserializeOwned>(
+    forge: &str,
+    request: reqwest::blocking::RequestBuilder,
+) -> Result<T> {
+    let response = request.send()?;
+    let status = response.status();
+    let body = response.text()?;
+
+    if !status.is_success() {
+        return Err(anyhow!(
+            "{forge} API request failed with status {status}: {}",
+            error_message(&body)
+        ));
+    }
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Short name of the branch checked out in `repo`
+pub fn current_branch(repo: &git2::Repository) -> Result<String> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err(anyhow!(
+            "HEAD is detached, check out the branch to open a pull request for"
+        ));
+    }
+    head.shorthand()
+        .map(String::from)
+        .ok_or(anyhow!("The current branch name is not valid UTF-8"))
+}
+
+/// Turn a commit-ish like `origin/main` into the branch name the forge knows
+pub fn branch_name(rev: &str) -> &str {
+    rev.strip_prefix("origin/")
+        .or_else(|| rev.strip_prefix("refs/heads/"))
+        .unwrap_or(rev)
+}
+
+/// The message of a failed API call, from the JSON `message` field when there is one
+pub(crate) fn error_message(body: &str) -> String {
+    let json: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+    let text = |value: &serde_json::Value| {
+        let value = value.get("message").unwrap_or(value);
+        value
+            .as_str()
+            .map(|m| m.trim().to_string())
+            .unwrap_or_else(|| value.to_string())
+    };
+
+    // GitLab sends validation failures as a list of strings in `message`
+    let message = match json.get("message") {
+        Some(serde_json::Value::Array(messages)) => {
+            Some(messages.iter().map(text).collect::<Vec<_>>().join(", "))
+        }
+        Some(message) => Some(text(message)),
+        None => None,
+    };
+    // GitHub puts the useful part of validation failures in `errors`, which is all
+    // Bitbucket sends
+    let details: Vec<String> = json
+        .get("errors")
+        .and_then(|errors| errors.as_array())
+        .into_iter()
+        .flatten()
+        .map(text)
+        .collect();
+
+    match message {
+        Some(message) if details.is_empty() => message,
+        Some(message) => format!("{message}: {}", details.join(", ")),
+        None if details.is_empty() => body.trim().to_string(),
+        None => details.join(", "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_remote() {
+        let remote = |host: &str, owner: &str, name: &str| Remote {
+            host: host.to_string(),
+            owner: owner.to_string(),
+            name: name.to_string(),
+        };
+
+        assert_eq!(
+            remote("github.com", "theelderbeever", "prai-cli"),
+            Remote::parse("git@github.com:theelderbeever/prai-cli.git").unwrap()
+        );
+        assert_eq!(
+            remote("github.com", "theelderbeever", "prai-cli"),
+            Remote::parse("https://github.com/theelderbeever/prai-cli").unwrap()
+        );
+        assert_eq!(
+            remote("gitlab.example.com", "group/subgroup", "api"),
+            Remote::parse("ssh://git@gitlab.example.com:2222/group/subgroup/api.git").unwrap()
+        );
+        assert_eq!(
+            remote("git.example.com", "team", "web"),
+            Remote::parse("https://ci-token@git.example.com:8443/team/web.git/").unwrap()
+        );
+        assert!(Remote::parse("not a remote").is_err());
+    }
+
+    #[test]
+    fn test_error_message() {
+        assert_eq!(
+            "Validation Failed: A pull request already exists",
+            error_message(
+                r#"{"message": "Validation Failed", "errors": [{"message": "A pull request already exists"}]}"#
+            )
+        );
+        assert_eq!(
+            "Another open merge request already exists for this source branch",
+            error_message(
+                r#"{"message": ["Another open merge request already exists for this source branch"]}"#
+            )
+        );
+        assert_eq!(
+            "Only one pull request may be open for a given source and target branch",
+            error_message(
+                r#"{"errors": [{"context": null, "message": "Only one pull request may be open for a given source and target branch"}]}"#
+            )
+        );
+        assert_eq!("Bad Gateway", error_message("Bad Gateway\n"));
+    }
+
+    #[test]
+    fn test_detect() {
+        let detect = |url: &str| detect(&Remote::parse(url).unwrap());
+
+        assert_eq!(Some(ForgeKind::GitHub), detect("git@github.com:o/r.git"));
+        assert_eq!(
+            Some(ForgeKind::GitHub),
+            detect("https://github.example.com/o/r")
+        );
+        assert_eq!(
+            Some(ForgeKind::GitLab),
+            detect("git@gitlab.internal:g/r.git")
+        );
+        assert_eq!(
+            Some(ForgeKind::Gitea),
+            detect("https://codeberg.org/o/r.git")
+        );
+        assert_eq!(
+            Some(ForgeKind::Gitea),
+            detect("git@forgejo.example.com:o/r.git")
+        );
+        assert_eq!(
+            Some(ForgeKind::Bitbucket),
+            detect("https://git.example.com/scm/PROJ/repo.git")
+        );
+        assert_eq!(
+            Some(ForgeKind::Bitbucket),
+            detect("ssh://git@bitbucket.example.com:7999/proj/repo.git")
+        );
+        assert_eq!(None, detect("git@bitbucket.org:o/r.git"));
+        assert_eq!(None, detect("git@git.example.com:o/r.git"));
+
+        assert_eq!(
+            "https://api.github.com",
+            api_url(ForgeKind::GitHub, "github.com")
+        );
+        assert_eq!(
+            "https://github.example.com/api/v3",
+            api_url(ForgeKind::GitHub, "github.example.com")
+        );
+        assert_eq!(
+            "https://git.example.com/api/v4",
+            api_url(ForgeKind::GitLab, "git.example.com")
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::Result;
+use globset::{Glob, GlobMatcher};
+
+use crate::git::ignore::Skipped;
+
+/// A structured diff between two trees
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diff {
+    pub files: Vec<FileChange>,
+    /// Changed files removed by `.praiignore` or `.gitattributes`
+    pub skipped: Vec<Skipped>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Render the diff in unified `git diff` format
+    pub fn to_patch(&self) -> String {
+        self.files.iter().map(|f| f.patch.as_str()).collect()
+    }
+}
+
+/// The changes made to a single file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: String,
+    /// Previous path when the file was renamed or copied
+    pub old_path: Option<String>,
+    pub status: ChangeStatus,
+    pub binary: bool,
+    pub additions: usize,
+    pub deletions: usize,
+    pub hunks: Vec<Hunk>,
+    /// Unified diff text for this file, including the `diff --git` header
+    pub patch: String,
+}
+
+impl FileChange {
+    /// Added and removed lines of the patch, without the `+++`/`---` file headers
+    pub fn changed_lines(&self) -> impl Iterator<Item = &str> {
+        self.patch
+            .lines()
+            .filter(|l| !l.starts_with("+++ ") && !l.starts_with("--- "))
+            .filter(|l| l.starts_with('+') || l.starts_with('-'))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeStatus {
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+    TypeChange,
+}
+
+impl From<git2::Delta> for ChangeStatus {
+    fn from(delta: git2::Delta) -> Self {
+        match delta {
+            git2::Delta::Added | git2::Delta::Untracked => Self::Added,
+            git2::Delta::Deleted => Self::Deleted,
+            git2::Delta::Renamed => Self::Renamed,
+            git2::Delta::Copied => Self::Copied,
+            git2::Delta::Typechange => Self::TypeChange,
+            _ => Self::Modified,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub header: String,
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+}
+
+/// Git style pathspecs. Patterns without glob characters match a file or directory
+/// prefix, patterns with globs match anywhere in the path and `:!`, `:^` or
+/// `:(exclude)` prefixed patterns exclude matching paths.
+#[derive(Debug, Clone, Default)]
+pub struct Pathspec {
+    includes: Vec<Pattern>,
+    excludes: Vec<Pattern>,
+}
+
+impl Pathspec {
+    pub fn new<S: AsRef<str>>(specs: &[S]) -> Result<Self> {
+        let mut pathspec = Self::default();
+        for spec in specs {
+            let spec = spec.as_ref().trim();
+            if spec.is_empty() {
+                continue;
+            }
+
+            match excluded(spec) {
+                Some(pattern) => pathspec.excludes.push(Pattern::new(pattern)?),
+                None => pathspec.includes.push(Pattern::new(spec)?),
+            }
+        }
+        Ok(pathspec)
+    }
+
+    /// Combine positive pathspecs with exclusions. Each exclusion is one pathspec, with or
+    /// without the `:!` prefix, like git. Entries where every space separated word is
+    /// prefixed, like `":!*.lock :!dist/"`, are still read as several exclusions.
+    pub fn from_filters<I: AsRef<str>, E: AsRef<str>>(
+        include: &[I],
+        exclude: &[E],
+    ) -> Result<Self> {
+        let mut pathspec = Self::new(include)?;
+        for exclude in exclude {
+            let exclude = exclude.as_ref().trim();
+            let words: Vec<&str> = exclude.split_whitespace().collect();
+            let patterns = if words.len() > 1 && words.iter().all(|word| excluded(word).is_some()) {
+                words
+            } else {
+                vec![exclude]
+            };
+            for pattern in patterns.into_iter().filter(|pattern| !pattern.is_empty()) {
+                pathspec
+                    .excludes
+                    .push(Pattern::new(excluded(pattern).unwrap_or(pattern))?);
+            }
+        }
+        Ok(pathspec)
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        (self.includes.is_empty() || self.includes.iter().any(|p| p.matches(path)))
+            && !self.excludes.iter().any(|p| p.matches(path))
+    }
+}
+
+/// The pattern of an exclude pathspec, without its magic prefix
+fn excluded(spec: &str) -> Option<&str> {
+    [":!", ":^", ":(exclude)"]
+        .iter()
+        .find_map(|prefix| spec.strip_prefix(prefix))
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Prefix(String),
+    Glob(GlobMatcher),
+}
+
+impl Pattern {
+    fn new(pattern: &str) -> Result<Self> {
+        let pattern = pattern
+            .trim_start_matches(":/")
+            .trim_start_matches(":(top)")
+            .trim_start_matches("./");
+
+        if pattern.contains(['*', '?', '[']) {
+            Ok(Self::Glob(Glob::new(pattern)?.compile_matcher()))
+        } else {
+            Ok(Self::Prefix(pattern.trim_end_matches('/').to_string()))
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Prefix(prefix) => {
+                prefix.is_empty()
+                    || path == prefix
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            }
+            Self::Glob(glob) => glob.is_match(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pathspec() {
+        let pathspec = Pathspec::new(&[":!*.lock", ":(exclude)dist/"]).unwrap();
+        assert!(pathspec.matches("src/main.rs"));
+        assert!(!pathspec.matches("Cargo.lock"));
+        assert!(!pathspec.matches("crates/api/Cargo.lock"));
+        assert!(!pathspec.matches("dist/index.js"));
+        assert!(pathspec.matches("distribution.md"));
+
+        let pathspec = Pathspec::new(&["crates/api", ":!*.lock"]).unwrap();
+        assert!(pathspec.matches("crates/api/src/lib.rs"));
+        assert!(!pathspec.matches("crates/api/Cargo.lock"));
+        assert!(!pathspec.matches("crates/web/src/lib.rs"));
+
+        let pathspec =
+            Pathspec::from_filters(&["crates/api/", "dist"], &[":!*.lock :!dist/", "*.snap"])
+                .unwrap();
+        assert!(pathspec.matches("crates/api/src/lib.rs"));
+        assert!(!pathspec.matches("crates/api/Cargo.lock"));
+        assert!(!pathspec.matches("dist/index.js"));
+        assert!(!pathspec.matches("crates/api/tests/snapshots/a.snap"));
+
+        let pathspec = Pathspec::from_filters::<&str, _>(&[], &[":!docs/My Notes/"]).unwrap();
+        assert!(!pathspec.matches("docs/My Notes/todo.md"));
+        assert!(pathspec.matches("Notes/todo.md"));
+        assert!(pathspec.matches("docs/My/todo.md"));
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, anyhow};
+
+/// Marker identifying hooks written by prai so foreign hooks are never touched
+const MARKER: &str = "# Installed by prai.";
+
+pub const PREPARE_COMMIT_MSG: &str = "prepare-commit-msg";
+
+/// Directory git runs hooks from: `core.hooksPath` when set, otherwise `.git/hooks`
+pub fn hooks_dir(repo: &git2::Repository) -> Result<PathBuf> {
+    let configured = repo
+        .config()
+        .and_then(|c| c.get_path("core.hooksPath"))
+        .ok();
+
+    Ok(match configured {
+        Some(path) if path.is_absolute() => path,
+        // Relative hook paths are resolved against the root of the working tree
+        Some(path) => repo
+            .workdir()
+            .ok_or(anyhow!(
+                "`core.hooksPath` is relative but the repository is bare"
+            ))?
+            .join(path),
+        None => repo.path().join("hooks"),
+    })
+}
+
+/// The `prepare-commit-msg` script. It only fills in plain `git commit` messages, leaving
+/// merges, amends, `-m` and templates alone, and never blocks the commit if prai fails.
+fn script(args: &[String]) -> String {
+    let args: String = args
+        .iter()
+        .map(|a| format!(" '{}'", a.replace('\'', "'\\''")))
+        .collect();
+    format!(
+        r#"#!/bin/sh
+{MARKER} Remove with `prai hook uninstall`.
+[ -n "$2" ] && exit 0
+message=$(prai commit{args} 2>/dev/null) || exit 0
+[ -z "$message" ] && exit 0
+{{ printf '%s\n' "$message"; cat "$1"; }} > "$1.prai" && mv "$1.prai" "$1"
+"#
+    )
+}
+
+/// Write the `prepare-commit-msg` hook, passing `args` through to `prai commit`. An existing
+/// hook which prai did not write is only replaced with `force`.
+pub fn install(repo: &git2::Repository, args: &[String], force: bool) -> Result<PathBuf> {
+    let dir = hooks_dir(repo)?;
+    let path = dir.join(PREPARE_COMMIT_MSG);
+
+    if let Ok(existing) = std::fs::read_to_string(&path)
+        && !existing.contains(MARKER)
+        && !force
+    {
+        return Err(anyhow!(
+            "`{}` already exists and was not installed by prai. Pass `--force` to replace it",
+            path.display()
+        ));
+    }
+
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Unable to create `{}`", dir.display()))?;
+    std::fs::write(&path, script(args))
+        .with_context(|| format!("Unable to write `{}`", path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(path)
+}
+
+/// Remove the `prepare-commit-msg` hook if prai installed it, returning its path
+pub fn uninstall(repo: &git2::Repository) -> Result<Option<PathBuf>> {
+    let path = hooks_dir(repo)?.join(PREPARE_COMMIT_MSG);
+
+    match std::fs::read_to_string(&path) {
+        Ok(existing) if existing.contains(MARKER) => {
+            std::fs::remove_file(&path)?;
+            Ok(Some(path))
+        }
+        Ok(_) => Err(anyhow!(
+            "`{}` was not installed by prai, leaving it in place",
+            path.display()
+        )),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_install_and_uninstall() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        repo.config()
+            .unwrap()
+            .set_str("core.hooksPath", ".githooks")
+            .unwrap();
+
+        let path = install(&repo, &[String::from("-p"), String::from("ollama")], false).unwrap();
+        assert_eq!(dir.path().join(".githooks/prepare-commit-msg"), path);
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("message=$(prai commit '-p' 'ollama' 2>/dev/null)")
+        );
+        // Reinstalling over our own hook is fine
+        install(&repo, &[], false).unwrap();
+
+        assert_eq!(Some(path.clone()), uninstall(&repo).unwrap());
+        assert!(!path.exists());
+        assert_eq!(None, uninstall(&repo).unwrap());
+
+        std::fs::write(&path, "#!/bin/sh\nexit 0\n").unwrap();
+        assert!(install(&repo, &[], false).is_err());
+        assert!(uninstall(&repo).is_err());
+        install(&repo, &[], true).unwrap();
+    }
+}
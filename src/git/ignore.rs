@@ -0,0 +1,103 @@
+use std::{fmt, path::Path};
+
+use anyhow::Result;
+use git2::{AttrCheckFlags, AttrValue};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::warn;
+
+/// File at the repository root listing paths to keep out of prompts, in gitignore syntax
+pub const PRAIIGNORE: &str = ".praiignore";
+
+/// Why a changed file was left out of the diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    PraiIgnore,
+    Generated,
+    Vendored,
+    NoDiff,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PraiIgnore => write!(f, "{PRAIIGNORE}"),
+            Self::Generated => write!(f, "linguist-generated"),
+            Self::Vendored => write!(f, "linguist-vendored"),
+            Self::NoDiff => write!(f, "-diff"),
+        }
+    }
+}
+
+/// A changed file which was automatically removed from the diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.path, self.reason)
+    }
+}
+
+/// Repository level rules for files which should never be sent to a model
+pub struct IgnoreRules<'r> {
+    repo: &'r git2::Repository,
+    praiignore: Gitignore,
+}
+
+impl<'r> IgnoreRules<'r> {
+    pub fn load(repo: &'r git2::Repository) -> Result<Self> {
+        let praiignore = match repo.workdir() {
+            Some(root) if root.join(PRAIIGNORE).is_file() => {
+                let mut builder = GitignoreBuilder::new(root);
+                if let Some(e) = builder.add(root.join(PRAIIGNORE)) {
+                    warn!("Problem reading {PRAIIGNORE}: {e}");
+                }
+                builder.build()?
+            }
+            _ => Gitignore::empty(),
+        };
+
+        Ok(Self { repo, praiignore })
+    }
+
+    /// The reason `path` should be left out of the diff, if any
+    pub fn skip_reason(&self, path: &str) -> Option<SkipReason> {
+        if self
+            .praiignore
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+        {
+            return Some(SkipReason::PraiIgnore);
+        }
+
+        let attr = |name: &str| {
+            let value = self
+                .repo
+                .get_attr(Path::new(path), name, AttrCheckFlags::FILE_THEN_INDEX)
+                .ok()
+                .flatten();
+            AttrValue::from_string(value)
+        };
+
+        if is_set(attr("linguist-generated")) {
+            Some(SkipReason::Generated)
+        } else if is_set(attr("linguist-vendored")) {
+            Some(SkipReason::Vendored)
+        } else if matches!(attr("diff"), AttrValue::False) {
+            Some(SkipReason::NoDiff)
+        } else {
+            None
+        }
+    }
+}
+
+fn is_set(value: AttrValue) -> bool {
+    match value {
+        AttrValue::True => true,
+        AttrValue::String(v) => v.eq_ignore_ascii_case("true"),
+        _ => false,
+    }
+}
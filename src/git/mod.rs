@@ -0,0 +1,628 @@
+pub mod diff;
+pub mod hook;
+pub mod ignore;
+
+use std::cell::Cell;
+
+use anyhow::{Context, Result, anyhow};
+use git2::{Cred, CredentialType, DiffFindOptions, DiffOptions, Oid, Sort};
+use log::debug;
+
+pub use diff::{ChangeStatus, Diff, FileChange, Hunk, Pathspec};
+pub use ignore::{IgnoreRules, SkipReason, Skipped};
+
+/// Branch names probed when the repository does not record its default branch
+const CANDIDATE_BRANCHES: &[&str] = &["main", "master", "develop"];
+
+/// Which changes are described
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiffMode {
+    /// Committed changes between two revisions
+    #[default]
+    Range,
+    /// Changes staged in the index, like `git diff --cached`
+    Staged,
+    /// Unstaged changes in the working tree, like `git diff`
+    WorkingTree,
+    /// Staged, unstaged and untracked changes
+    All,
+}
+
+/// A commit in the range being described
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub id: Oid,
+    pub summary: String,
+    /// Message body without the subject or trailers
+    pub body: String,
+    /// `Key: value` trailers such as `Fixes:` or `Co-authored-by:`
+    pub trailers: Vec<(String, String)>,
+    pub author: String,
+    /// Seconds since the unix epoch
+    pub time: i64,
+}
+
+/// In-process access to the git repository containing the working directory
+pub struct Repository {
+    repo: git2::Repository,
+}
+
+impl Repository {
+    /// Open the repository for the current directory, honoring `GIT_DIR` and friends
+    pub fn open() -> Result<Self> {
+        let repo = git2::Repository::open_from_env().context("Not inside a git repository")?;
+        Ok(Self { repo })
+    }
+
+    pub fn from_git2(repo: git2::Repository) -> Self {
+        Self { repo }
+    }
+
+    pub fn inner(&self) -> &git2::Repository {
+        &self.repo
+    }
+
+    /// Resolve any commit-ish (branch, tag, sha, `HEAD~2`, ...) to a commit id
+    pub fn resolve(&self, rev: &str) -> Result<Oid> {
+        Ok(self
+            .repo
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("Unable to resolve `{rev}` to a commit"))?
+            .id())
+    }
+
+    /// The best common ancestor of two commit-ish revisions, the point `head` branched from `base`
+    pub fn merge_base(&self, base: &str, head: &str) -> Result<Oid> {
+        self.repo
+            .merge_base(self.resolve(base)?, self.resolve(head)?)
+            .with_context(|| format!("`{base}` and `{head}` have no common history"))
+    }
+
+    /// Diff the trees of two commit-ish revisions, keeping only paths matching `pathspec`
+    pub fn diff(&self, base: &str, head: &str, pathspec: &Pathspec) -> Result<Diff> {
+        let base_tree = self.repo.find_commit(self.resolve(base)?)?.tree()?;
+        let head_tree = self.repo.find_commit(self.resolve(head)?)?.tree()?;
+
+        let mut diff = self.repo.diff_tree_to_tree(
+            Some(&base_tree),
+            Some(&head_tree),
+            Some(&mut diff_options()),
+        )?;
+        diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+
+        let diff = collect(&diff, pathspec, &IgnoreRules::load(&self.repo)?)?;
+        if diff.is_empty() {
+            return Err(anyhow!("No differences between `{base}` and `{head}`"));
+        }
+
+        Ok(diff)
+    }
+
+    /// Diff changes which have not been committed yet. Staged and all changes are compared
+    /// against `base`, working tree changes against the index.
+    pub fn diff_uncommitted(
+        &self,
+        mode: DiffMode,
+        base: &str,
+        pathspec: &Pathspec,
+    ) -> Result<Diff> {
+        let base_tree = match self
+            .repo
+            .revparse_single(base)
+            .and_then(|o| o.peel_to_tree())
+        {
+            Ok(tree) => Some(tree),
+            // Nothing has been committed yet, everything is new
+            Err(_) if base == "HEAD" && self.repo.head().is_err() => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Unable to resolve `{base}` to a tree"));
+            }
+        };
+
+        let mut options = diff_options();
+        let mut diff = match mode {
+            DiffMode::Range => return Err(anyhow!("A revision range is not an uncommitted diff")),
+            DiffMode::Staged => {
+                self.repo
+                    .diff_tree_to_index(base_tree.as_ref(), None, Some(&mut options))?
+            }
+            DiffMode::WorkingTree => self.repo.diff_index_to_workdir(None, Some(&mut options))?,
+            DiffMode::All => {
+                options
+                    .include_untracked(true)
+                    .recurse_untracked_dirs(true)
+                    .show_untracked_content(true);
+                self.repo
+                    .diff_tree_to_workdir_with_index(base_tree.as_ref(), Some(&mut options))?
+            }
+        };
+        diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+
+        let diff = collect(&diff, pathspec, &IgnoreRules::load(&self.repo)?)?;
+        if diff.is_empty() {
+            return Err(match mode {
+                DiffMode::Staged => anyhow!("No staged changes"),
+                DiffMode::WorkingTree => anyhow!("No unstaged changes"),
+                _ => anyhow!("No uncommitted changes"),
+            });
+        }
+
+        Ok(diff)
+    }
+
+    /// Commits reachable from `head` but not from `base`, oldest first
+    pub fn commits(&self, base: &str, head: &str) -> Result<Vec<Commit>> {
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        revwalk.push(self.resolve(head)?)?;
+        revwalk.hide(self.resolve(base)?)?;
+
+        revwalk
+            .map(|oid| {
+                let commit = self.repo.find_commit(oid?)?;
+                let message = commit.message().unwrap_or_default();
+                let trailers: Vec<_> = git2::message_trailers_strs(message)?
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect();
+
+                // Trailers are always the last paragraph of the message
+                let mut body = commit.body().unwrap_or_default().trim();
+                if !trailers.is_empty() {
+                    body = body
+                        .rsplit_once("\n\n")
+                        .map_or("", |(body, _)| body.trim_end());
+                }
+
+                Ok(Commit {
+                    id: commit.id(),
+                    summary: commit.summary().unwrap_or_default().to_string(),
+                    body: body.to_string(),
+                    trailers,
+                    author: commit.author().name().unwrap_or_default().to_string(),
+                    time: commit.time().seconds(),
+                })
+            })
+            .collect()
+    }
+
+    /// Work out the branch changes are usually merged into without touching the network.
+    /// Checks `refs/remotes/origin/HEAD`, then `init.defaultBranch`, then probes common
+    /// branch names. The remote is only asked when `query_remote` is set.
+    pub fn default_branch(&self, query_remote: bool) -> Result<String> {
+        let origin_head = self
+            .repo
+            .find_reference("refs/remotes/origin/HEAD")
+            .ok()
+            .and_then(|r| r.symbolic_target().map(String::from))
+            .and_then(|target| {
+                target
+                    .strip_prefix("refs/remotes/origin/")
+                    .and_then(|name| self.existing_branch(name))
+            });
+        if let Some(branch) = origin_head {
+            debug!("Default branch `{branch}` from refs/remotes/origin/HEAD");
+            return Ok(branch);
+        }
+
+        let configured = self
+            .repo
+            .config()
+            .and_then(|c| c.get_string("init.defaultBranch"))
+            .ok()
+            .and_then(|name| self.existing_branch(&name));
+        if let Some(branch) = configured {
+            debug!("Default branch `{branch}` from init.defaultBranch");
+            return Ok(branch);
+        }
+
+        if let Some(branch) = CANDIDATE_BRANCHES
+            .iter()
+            .find_map(|name| self.existing_branch(name))
+        {
+            debug!("Default branch `{branch}` found by probing common names");
+            return Ok(branch);
+        }
+
+        if query_remote && let Some(branch) = self.remote_default_branch("origin")? {
+            debug!("Default branch `{branch}` reported by origin");
+            return Ok(branch);
+        }
+
+        Err(anyhow!(
+            "Unable to determine the default branch. Pass the base explicitly or use `--query-remote`"
+        ))
+    }
+
+    /// A local branch named `name`, falling back to its `origin` remote-tracking branch
+    fn existing_branch(&self, name: &str) -> Option<String> {
+        if self.repo.find_branch(name, git2::BranchType::Local).is_ok() {
+            Some(name.to_string())
+        } else if self
+            .repo
+            .find_branch(&format!("origin/{name}"), git2::BranchType::Remote)
+            .is_ok()
+        {
+            Some(format!("origin/{name}"))
+        } else {
+            None
+        }
+    }
+
+    /// Ask a remote for its default branch. This is a network round trip.
+    pub fn remote_default_branch(&self, name: &str) -> Result<Option<String>> {
+        let mut remote = self.repo.find_remote(name)?;
+        let config = self.repo.config()?;
+        let connection = remote.connect_auth(
+            git2::Direction::Fetch,
+            Some(remote_callbacks(&config)),
+            None,
+        )?;
+        let branch = connection.default_branch()?;
+
+        Ok(branch
+            .as_str()
+            .map(|b| b.trim_start_matches("refs/heads/").to_string())
+            .filter(|b| !b.is_empty()))
+    }
+}
+
+fn diff_options() -> DiffOptions {
+    let mut options = DiffOptions::new();
+    options.context_lines(3).ignore_submodules(true);
+    options
+}
+
+/// Convert a libgit2 diff into the typed representation, dropping unmatched paths
+/// and recording the files removed by the repository's ignore rules
+pub(crate) fn collect(diff: &git2::Diff, pathspec: &Pathspec, rules: &IgnoreRules) -> Result<Diff> {
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+
+    for idx in 0..diff.deltas().len() {
+        let Some(mut patch) = git2::Patch::from_diff(diff, idx)? else {
+            continue;
+        };
+        let delta = patch.delta();
+
+        let path = delta
+            .new_file()
+            .path()
+            .or(delta.old_file().path())
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !pathspec.matches(&path) {
+            continue;
+        }
+        if let Some(reason) = rules.skip_reason(&path) {
+            skipped.push(Skipped { path, reason });
+            continue;
+        }
+
+        let old_path = delta
+            .old_file()
+            .path()
+            .map(|p| p.to_string_lossy().to_string())
+            .filter(|old| {
+                matches!(delta.status(), git2::Delta::Renamed | git2::Delta::Copied) && *old != path
+            });
+        let status = ChangeStatus::from(delta.status());
+        let binary = delta.flags().is_binary();
+
+        let hunks = (0..patch.num_hunks())
+            .map(|i| {
+                let (hunk, _) = patch.hunk(i)?;
+                Ok(Hunk {
+                    header: String::from_utf8_lossy(hunk.header())
+                        .trim_end()
+                        .to_string(),
+                    old_start: hunk.old_start(),
+                    old_lines: hunk.old_lines(),
+                    new_start: hunk.new_start(),
+                    new_lines: hunk.new_lines(),
+                })
+            })
+            .collect::<Result<Vec<_>, git2::Error>>()?;
+        let (_, additions, deletions) = patch.line_stats()?;
+        let patch = String::from_utf8_lossy(&patch.to_buf()?).to_string();
+
+        files.push(FileChange {
+            path,
+            old_path,
+            status,
+            binary,
+            additions,
+            deletions,
+            hunks,
+            patch,
+        });
+    }
+
+    Ok(Diff { files, skipped })
+}
+
+/// Credentials for talking to a remote: the ssh agent or the configured credential helper
+fn remote_callbacks(config: &git2::Config) -> git2::RemoteCallbacks<'_> {
+    let attempts = Cell::new(0);
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        // libgit2 keeps asking for credentials until one works, give up instead of looping
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > 3 {
+            return Err(git2::Error::from_str(
+                "Unable to authenticate with the remote",
+            ));
+        }
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            Cred::credential_helper(config, url, username)
+        } else {
+            Cred::default()
+        }
+    });
+    callbacks
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    /// Commit the given files on top of `HEAD` in a scratch repository
+    pub(crate) fn commit(
+        repo: &git2::Repository,
+        message: &str,
+        files: &[(&str, Option<&str>)],
+    ) -> Oid {
+        let root = repo.workdir().unwrap();
+        let mut index = repo.index().unwrap();
+        for (path, content) in files {
+            match content {
+                Some(content) => {
+                    let file = root.join(path);
+                    std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+                    std::fs::write(file, content).unwrap();
+                    index.add_path(Path::new(path)).unwrap();
+                }
+                None => {
+                    std::fs::remove_file(root.join(path)).unwrap();
+                    index.remove_path(Path::new(path)).unwrap();
+                }
+            }
+        }
+        index.write().unwrap();
+
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("prai", "prai@example.com").unwrap();
+        let parents: Vec<_> = repo
+            .head()
+            .ok()
+            .and_then(|h| h.peel_to_commit().ok())
+            .into_iter()
+            .collect();
+        let parents: Vec<_> = parents.iter().collect();
+
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_typed_diff_and_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::from_git2(git2::Repository::init(dir.path()).unwrap());
+        let base = commit(
+            repo.inner(),
+            "Initial commit",
+            &[
+                ("src/lib.rs", Some("pub fn a() {}\n")),
+                ("Cargo.lock", Some("version = 1\n")),
+                (
+                    "docs/old.md",
+                    Some("# Docs\n\nSome long enough content to detect a rename\n"),
+                ),
+            ],
+        );
+        commit(
+            repo.inner(),
+            "Add b\n\nExplain why b exists.\n\nFixes: #12\nCo-authored-by: Ada <ada@example.com>\n",
+            &[
+                ("src/lib.rs", Some("pub fn a() {}\npub fn b() {}\n")),
+                ("Cargo.lock", Some("version = 2\n")),
+                (".gitattributes", Some("*.pb.go linguist-generated\n")),
+                (".praiignore", Some("snapshots/\n")),
+                ("api/service.pb.go", Some("package api\n")),
+                ("snapshots/render.snap", Some("<div />\n")),
+                ("docs/old.md", None),
+                (
+                    "docs/new.md",
+                    Some("# Docs\n\nSome long enough content to detect a rename\n"),
+                ),
+            ],
+        );
+
+        let diff = repo
+            .diff(
+                &base.to_string(),
+                "HEAD",
+                &Pathspec::new(&[":!*.lock"]).unwrap(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                (".gitattributes", None, ChangeStatus::Added),
+                (".praiignore", None, ChangeStatus::Added),
+                ("docs/new.md", Some("docs/old.md"), ChangeStatus::Renamed),
+                ("src/lib.rs", None, ChangeStatus::Modified),
+            ],
+            diff.files
+                .iter()
+                .map(|f| (f.path.as_str(), f.old_path.as_deref(), f.status))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                "api/service.pb.go (linguist-generated)",
+                "snapshots/render.snap (.praiignore)",
+            ],
+            diff.skipped
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+        let lib = &diff.files[3];
+        assert_eq!((1, 0, 1), (lib.additions, lib.deletions, lib.hunks.len()));
+        assert!(
+            lib.patch
+                .starts_with("diff --git a/src/lib.rs b/src/lib.rs")
+        );
+
+        let commits = repo.commits(&base.to_string(), "HEAD").unwrap();
+        assert_eq!(1, commits.len());
+        assert_eq!("Add b", commits[0].summary);
+        assert_eq!("Explain why b exists.", commits[0].body);
+        assert_eq!(
+            vec![
+                (String::from("Fixes"), String::from("#12")),
+                (
+                    String::from("Co-authored-by"),
+                    String::from("Ada <ada@example.com>")
+                ),
+            ],
+            commits[0].trailers
+        );
+    }
+
+    #[test]
+    fn test_merge_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        let root = commit(&repo, "Initial commit", &[("README.md", Some("# prai\n"))]);
+        let main = repo.head().unwrap().name().unwrap().to_string();
+        repo.branch("feature", &repo.find_commit(root).unwrap(), false)
+            .unwrap();
+        commit(
+            &repo,
+            "Land on main",
+            &[("CHANGELOG.md", Some("## 0.3.1\n"))],
+        );
+        repo.set_head("refs/heads/feature").unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        commit(
+            &repo,
+            "Add feature",
+            &[("src/lib.rs", Some("pub fn a() {}\n"))],
+        );
+        let repository = Repository::from_git2(repo);
+
+        let merge_base = repository.merge_base(&main, "feature").unwrap();
+        assert_eq!(root, merge_base);
+
+        let paths = |base: &str| {
+            repository
+                .diff(base, "feature", &Pathspec::default())
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|f| f.path)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["src/lib.rs"], paths(&merge_base.to_string()));
+        assert_eq!(vec!["CHANGELOG.md", "src/lib.rs"], paths(&main));
+    }
+
+    #[test]
+    fn test_uncommitted_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        commit(
+            &repo,
+            "Initial commit",
+            &[("src/lib.rs", Some("pub fn a() {}\n"))],
+        );
+        let repository = Repository::from_git2(repo);
+        let root = dir.path();
+
+        std::fs::write(root.join("src/lib.rs"), "pub fn b() {}\n").unwrap();
+        let mut index = repository.inner().index().unwrap();
+        index.add_path(Path::new("src/lib.rs")).unwrap();
+        index.write().unwrap();
+        std::fs::write(root.join("src/lib.rs"), "pub fn c() {}\n").unwrap();
+        std::fs::write(root.join("notes.md"), "todo\n").unwrap();
+
+        let paths = |mode| {
+            let diff = repository
+                .diff_uncommitted(mode, "HEAD", &Pathspec::default())
+                .unwrap();
+            diff.files
+                .iter()
+                .map(|f| (f.path.clone(), f.patch.lines().last().unwrap().to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![(String::from("src/lib.rs"), String::from("+pub fn b() {}"))],
+            paths(DiffMode::Staged)
+        );
+        assert_eq!(
+            vec![(String::from("src/lib.rs"), String::from("+pub fn c() {}"))],
+            paths(DiffMode::WorkingTree)
+        );
+        assert_eq!(
+            vec![
+                (String::from("notes.md"), String::from("+todo")),
+                (String::from("src/lib.rs"), String::from("+pub fn c() {}")),
+            ],
+            paths(DiffMode::All)
+        );
+    }
+
+    #[test]
+    fn test_default_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::from_git2(
+            git2::Repository::init_opts(
+                dir.path(),
+                git2::RepositoryInitOptions::new().initial_head("feature"),
+            )
+            .unwrap(),
+        );
+        let oid = commit(
+            repo.inner(),
+            "Initial commit",
+            &[("README.md", Some("# prai\n"))],
+        );
+        assert!(repo.default_branch(false).is_err());
+        let commit = repo.inner().find_commit(oid).unwrap();
+
+        repo.inner().branch("master", &commit, true).unwrap();
+        assert_eq!("master", repo.default_branch(false).unwrap());
+
+        repo.inner()
+            .reference("refs/remotes/origin/trunk", oid, true, "")
+            .unwrap();
+        repo.inner()
+            .reference_symbolic(
+                "refs/remotes/origin/HEAD",
+                "refs/remotes/origin/trunk",
+                true,
+                "",
+            )
+            .unwrap();
+        assert_eq!("origin/trunk", repo.default_branch(false).unwrap());
+    }
+}
@@ -1,5 +1,13 @@
+pub mod budget;
+pub mod changelog;
+pub mod ci;
+pub mod compare;
+pub mod cost;
+pub mod forge;
 pub mod git;
 pub mod providers;
+pub mod redact;
+pub mod review;
 pub mod settings;
 
 pub use providers::prompt::Prompt;
@@ -1,18 +1,30 @@
-use std::{fs::File, io::Read, path::PathBuf, time::Duration};
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
 use lazy_static::lazy_static;
 use log::debug;
 use rand::prelude::IndexedRandom;
 
 use prai::{
+    Prompt, ci,
+    compare::{self, Comparison},
+    cost::{self, Spend},
+    forge::{self, Forge, NewPullRequest},
+    git::{self, DiffMode, Repository},
     providers::{
-        Provider as _Provider, Request, anthropic::AnthropicProvider, google::GoogleProvider,
-        ollama::OllamaProvider, openai::OpenAIProvider,
+        Meter, Output, Provider as _Provider, Request, anthropic::AnthropicProvider,
+        fallback::FallbackProvider, google::GoogleProvider, ollama::OllamaProvider,
+        openai::OpenAIProvider,
     },
-    settings::{Provider, Settings},
+    review::Review,
+    settings::{Profile, Provider, Settings},
 };
 
 fn default_config_string() -> &'static str {
@@ -52,19 +64,28 @@ fn default_pr_template() -> Option<&'static str> {
 #[command(name = "prai")]
 #[command(about = "Generate PR descriptions from git diffs using configurable AI providers")]
 struct Args {
-    #[arg(default_value = prai::git::get_default_branch())]
-    minus: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Base commit-ish. Defaults to the repository's default branch
+    minus: Option<String>,
 
     #[arg(default_value = "HEAD")]
     plus: String,
 
-    /// File patterns to ignore in the git diff
-    #[arg(short, long, default_value = ":!*.lock")]
+    /// Pathspec to ignore in the git diff, in addition to the configured defaults. Repeat
+    /// it for several
+    #[arg(short, long)]
     exclude: Vec<String>,
 
+    /// Only include paths matching these pathspecs in the git diff
+    #[arg(short, long)]
+    include: Vec<String>,
+
     /// The provider profile to use for generation. Will default to the value in the config default.
+    /// Repeat it to pick the profiles for `prai compare`
     #[arg(short, long, global = true)]
-    profile: Option<String>,
+    profile: Vec<String>,
 
     /// Path to config file for sourcing providers
     #[arg(short = 'f', long = "config", global = true, default_value = default_config_string())]
@@ -78,18 +99,311 @@ struct Args {
     #[arg(short = 'T', long)]
     title: bool,
 
+    /// Generate the title and description from a single request and print them as
+    /// the title, a blank line and the description
+    #[arg(short = 'C', long, conflicts_with_all = ["title", "stream"])]
+    combined: bool,
+
+    /// With `--combined`, write the description to this file and print only the title
+    #[arg(long, requires = "combined")]
+    body_file: Option<PathBuf>,
+
+    /// Describe the changes staged for commit instead of a commit range
+    #[arg(long, group = "mode")]
+    staged: bool,
+
+    /// Describe the unstaged changes in the working tree instead of a commit range
+    #[arg(long, group = "mode")]
+    working_tree: bool,
+
+    /// Describe all uncommitted changes, including untracked files
+    #[arg(long, group = "mode")]
+    all_changes: bool,
+
+    /// Diff the base directly instead of the point the head branched from it
+    #[arg(long)]
+    two_dot: bool,
+
+    /// Include the commit messages between base and head in the prompt
+    #[arg(long)]
+    commits: bool,
+
+    /// Ask `origin` for its default branch when it cannot be determined locally
+    #[arg(long)]
+    query_remote: bool,
+
+    /// Print plain text, or the title, description and metadata as a JSON object
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Print the generated text as it is produced instead of waiting for the full response.
+    /// Only for plain text descriptions, titles and commit messages
+    #[arg(long)]
+    stream: bool,
+
+    /// Summarize large diffs in chunks and combine the summaries into the final description
+    #[arg(long)]
+    map_reduce: bool,
+
+    /// Run non-interactively: take the base from the CI environment, fetch missing history,
+    /// hide the spinner and write to the job summary. Detected on GitHub Actions, GitLab CI
+    /// and Buildkite
+    #[arg(long, global = true)]
+    ci: bool,
+
+    /// Also write the result to this file, for later steps of a pipeline
+    #[arg(long, global = true)]
+    output_file: Option<PathBuf>,
+
+    /// Print the tokens used and their estimated cost to stderr
+    #[arg(long, global = true)]
+    show_usage: bool,
+
     /// Verbose mode (-v, -vv, -vvv)
     #[arg(short, action = clap::ArgAction::Count)]
     verbose: u8,
 }
 
-impl Args {}
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a commit message from the staged changes
+    Commit {
+        /// Format the message following Conventional Commits
+        #[arg(short, long)]
+        conventional: bool,
+    },
+    /// Write Keep a Changelog release notes for the commits between two tags
+    Release {
+        /// Tag of the previous release
+        from: String,
+
+        /// Tag being released
+        #[arg(default_value = "HEAD")]
+        to: String,
+
+        /// Version for the section heading. Defaults to the tag being released, or
+        /// `Unreleased` for notes up to `HEAD`
+        #[arg(long)]
+        version: Option<String>,
+
+        /// Prepend the notes into a changelog file instead of printing them
+        #[arg(long, num_args = 0..=1, default_missing_value = "CHANGELOG.md")]
+        changelog: Option<PathBuf>,
+    },
+    /// Review the changes and report findings per file and line range
+    Review {
+        /// Base commit-ish. Defaults to the repository's default branch
+        minus: Option<String>,
+
+        #[arg(default_value = "HEAD")]
+        plus: String,
+
+        /// How to print the findings
+        #[arg(long, value_enum, default_value_t = ReviewFormat::Text)]
+        format: ReviewFormat,
+    },
+    /// Manage the `prepare-commit-msg` hook which pre-fills commit messages
+    Hook {
+        #[command(subcommand)]
+        action: HookAction,
+    },
+    /// Open or update the pull request for the current branch on GitHub, GitLab, Gitea or
+    /// Bitbucket
+    Pr {
+        #[command(subcommand)]
+        action: PrAction,
+    },
+    /// Send the same prompt to several profiles at once and show their descriptions side by
+    /// side with the latency and token usage of each
+    Compare {
+        /// Base commit-ish. Defaults to the repository's default branch
+        minus: Option<String>,
+
+        #[arg(default_value = "HEAD")]
+        plus: String,
+
+        /// Compare PR titles instead of descriptions
+        #[arg(short = 'T', long)]
+        title: bool,
+
+        /// Print the results side by side, or as a JSON array
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReviewFormat {
+    Text,
+    Json,
+    Sarif,
+}
+
+#[derive(Subcommand)]
+enum HookAction {
+    /// Install the hook into `.git/hooks` or `core.hooksPath`
+    Install {
+        /// Pass `--conventional` to `prai commit` from the hook
+        #[arg(short, long)]
+        conventional: bool,
+
+        /// Replace an existing hook which was not installed by prai
+        #[arg(long)]
+        force: bool,
+    },
+    /// Remove the hook if it was installed by prai
+    Uninstall,
+}
+
+#[derive(Subcommand)]
+enum PrAction {
+    /// Open a pull request from the current branch with a generated title and description
+    Create {
+        /// Branch to merge into. Defaults to the repository's default branch
+        minus: Option<String>,
+
+        /// Open the pull request as a draft
+        #[arg(long)]
+        draft: bool,
+    },
+    /// Regenerate the description of the open pull request for the current branch
+    Update {
+        /// Branch the pull request merges into. Defaults to the repository's default branch
+        minus: Option<String>,
+    },
+}
+
+impl PrAction {
+    fn minus(&self) -> Option<&String> {
+        match self {
+            Self::Create { minus, .. } | Self::Update { minus } => minus.as_ref(),
+        }
+    }
+}
+
+impl Args {
+    fn output(&self) -> Output {
+        match self.command {
+            Some(Command::Commit { conventional }) => Output::CommitMessage { conventional },
+            Some(Command::Release { .. }) => Output::ReleaseNotes,
+            Some(Command::Review { .. }) => Output::Review,
+            Some(Command::Pr { .. }) => Output::Structured,
+            Some(Command::Compare { title: true, .. }) => Output::Title,
+            Some(Command::Compare { .. }) => Output::Description,
+            _ if self.format == Format::Json || self.combined => Output::Structured,
+            _ if self.title => Output::Title,
+            _ => Output::Description,
+        }
+    }
+
+    /// The profile to generate with, only `prai compare` takes more than one
+    fn profile(&self) -> Result<Option<String>> {
+        match self.profile.as_slice() {
+            [] => Ok(None),
+            [profile] => Ok(Some(profile.clone())),
+            _ if matches!(self.command, Some(Command::Compare { .. })) => {
+                Ok(self.profile.first().cloned())
+            }
+            _ => Err(anyhow::anyhow!(
+                "Only `prai compare` takes more than one `--profile`"
+            )),
+        }
+    }
+
+    /// Reject flags which would be ignored by the command they were given to
+    fn check_flags(&self) -> Result<()> {
+        let subcommand = match self.command {
+            Some(Command::Pr { .. }) => Some("pr"),
+            Some(Command::Release { .. }) => Some("release"),
+            Some(Command::Review { .. }) => Some("review"),
+            Some(Command::Compare { .. }) => Some("compare"),
+            Some(Command::Hook { .. }) => Some("hook"),
+            _ => None,
+        };
+
+        if self.stream {
+            if let Some(subcommand) = subcommand {
+                return Err(anyhow::anyhow!(
+                    "`prai {subcommand}` does not stream its output, drop `--stream`"
+                ));
+            }
+            if self.format == Format::Json {
+                return Err(anyhow::anyhow!(
+                    "`--stream` cannot be used with `--format json`, the JSON is only printed once it is complete"
+                ));
+            }
+        }
+        if self.combined {
+            let subcommand = match self.command {
+                Some(Command::Commit { .. }) => Some("commit"),
+                _ => subcommand,
+            };
+            if let Some(subcommand) = subcommand {
+                return Err(anyhow::anyhow!(
+                    "`--combined` only applies to pull request descriptions, not `prai {subcommand}`"
+                ));
+            }
+            if self.format == Format::Json {
+                return Err(anyhow::anyhow!(
+                    "`--combined` cannot be used with `--format json`, which already has the title and description"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn mode(&self) -> DiffMode {
+        if matches!(self.command, Some(Command::Commit { .. })) || self.staged {
+            DiffMode::Staged
+        } else if self.working_tree {
+            DiffMode::WorkingTree
+        } else if self.all_changes {
+            DiffMode::All
+        } else {
+            DiffMode::Range
+        }
+    }
+
+    /// Code fence language for results which are not markdown
+    fn language(&self) -> Option<&'static str> {
+        match self.command {
+            Some(Command::Review {
+                format: ReviewFormat::Json | ReviewFormat::Sarif,
+                ..
+            }) => Some("json"),
+            Some(Command::Review { .. }) => Some("text"),
+            Some(Command::Compare {
+                format: Format::Json,
+                ..
+            }) => Some("json"),
+            Some(Command::Compare { .. }) => Some("text"),
+            None if self.format == Format::Json => Some("json"),
+            _ => None,
+        }
+    }
+}
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    args.check_flags()?;
+    if let Some(Command::Hook { action }) = &args.command {
+        return hook(&args, action);
+    }
+    let ci = ci::Context::detect();
     let mut rng = rand::rng();
 
-    let pb = ProgressBar::new_spinner();
+    // CI logs are not terminals, the spinner would only add noise
+    let pb = if args.ci || ci.is_some() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new_spinner()
+    };
     pb.enable_steady_tick(Duration::from_millis(17));
     pb.set_style(
         ProgressStyle::with_template("{spinner:.green} {msg}")
@@ -110,53 +424,490 @@ fn main() -> Result<()> {
         .filter_level(log_level)
         .init();
 
-    debug!("Using commit1: {}, commit2: {:?}", args.minus, args.plus);
-    debug!("Exclude pattern: {:?}", args.exclude);
+    let repo = Repository::open()?;
+    let mode = args.mode();
+    let output = args.output();
+    let (minus, plus) = match &args.command {
+        Some(Command::Release { from, to, .. }) => (Some(from.clone()), to.clone()),
+        Some(Command::Review { minus, plus, .. } | Command::Compare { minus, plus, .. }) => {
+            (minus.clone(), plus.clone())
+        }
+        Some(Command::Pr { action }) => (action.minus().cloned(), String::from("HEAD")),
+        _ => (args.minus.clone(), args.plus.clone()),
+    };
+    let minus = match minus {
+        Some(minus) => minus,
+        // Uncommitted work is compared against the last commit
+        None if mode != DiffMode::Range => String::from("HEAD"),
+        None => match ci
+            .as_ref()
+            .and_then(|ci| ci.base.clone().map(|base| (ci, base)))
+        {
+            Some((ci, base)) => {
+                debug!("Using the pull request base `{base}` from {}", ci.ci.name());
+                ci.fetch_history(&plus)?;
+                base
+            }
+            None => repo.default_branch(args.query_remote)?,
+        },
+    };
 
     let settings = Settings::from_path(&args.config)?;
-    let profile = settings.get(args.profile.clone())?;
+    // Find the token before spending a request on a pull request that cannot be opened
+    let forge = match &args.command {
+        Some(Command::Pr { .. }) => Some(forge::open(repo.inner(), &settings.forges)?),
+        _ => None,
+    };
+    let mut exclude = settings.excludes_for(repo.inner().workdir());
+    let global_redact = settings.redact.clone();
+    let profile = settings.get(args.profile()?)?;
+    let compared = match &args.command {
+        Some(Command::Compare { .. }) if args.profile.len() < 2 => {
+            return Err(anyhow::anyhow!(
+                "`prai compare` needs at least two profiles, pass `--profile` for each"
+            ));
+        }
+        Some(Command::Compare { .. }) => args
+            .profile
+            .iter()
+            .map(|name| settings.get(Some(name.clone())))
+            .collect::<Result<Vec<_>>>()?,
+        _ => Vec::new(),
+    };
+    if !compared.is_empty() && args.map_reduce {
+        return Err(anyhow::anyhow!(
+            "`prai compare` sends one prompt to every profile and cannot use `--map-reduce`"
+        ));
+    }
+    // Compared profiles share one prompt, which is redacted if any of them asks for it
+    let redact = if compared.is_empty() {
+        profile.redact_settings(&global_redact)
+    } else {
+        prai::redact::strictest(
+            compared
+                .iter()
+                .filter_map(|profile| profile.redact_settings(&global_redact)),
+        )
+    };
+    exclude.extend(profile.exclude.iter().cloned());
+    exclude.extend(args.exclude.iter().cloned());
+
+    debug!("Using commit1: {}, commit2: {:?}", minus, plus);
+    debug!("Include pattern: {:?}", args.include);
+    debug!("Exclude pattern: {:?}", exclude);
 
-    let template = args.template.and_then(|p| {
+    let template = args.template.as_ref().and_then(|p| {
         let mut content = String::new();
         let mut f = File::open(p).ok()?;
         f.read_to_string(&mut content).ok()?;
         Some(content)
     });
 
+    let meter = Meter::new(&profile.name);
     let request = Request::builder()
-        .base(args.minus.clone())
-        .exclude(args.exclude.clone())
-        .head(args.plus.clone())
+        .base(minus)
+        .mode(mode)
+        .two_dot(args.two_dot)
+        .commits(args.commits || profile.commits || output == Output::ReleaseNotes)
+        .include(args.include.clone())
+        .exclude(exclude)
+        .head(plus)
         .maybe_template(template)
         .maybe_role(profile.role.clone())
-        .maybe_directive(profile.directive.clone())
-        .is_title(args.title)
+        // Profile directives are written for pull requests
+        .maybe_directive(
+            profile
+                .directive
+                .clone()
+                .filter(|_| matches!(output, Output::Description | Output::Title)),
+        )
+        .output(output)
+        .retry(profile.retry.clone())
+        .max_continuations(profile.max_continuations)
+        // Compared profiles share one prompt, which has to fit all of them
+        .maybe_max_prompt_tokens(if compared.is_empty() {
+            profile.prompt_token_budget()
+        } else {
+            compared
+                .iter()
+                .filter_map(Profile::prompt_token_budget)
+                .min()
+        })
+        .maybe_map_reduce(
+            (args.map_reduce && !matches!(output, Output::ReleaseNotes | Output::Review))
+                .then(|| profile.map_reduce.clone()),
+        )
+        .maybe_redact(redact)
+        .meter(meter.clone())
+        .report_usage(args.show_usage)
         .build();
 
-    let description = match profile.provider {
-        Provider::Ollama(config) => {
-            let provider = OllamaProvider::from_config(config);
-            provider.make_request(request)
+    let result = match (&args.command, profile.provider) {
+        (Some(Command::Compare { format, .. }), _) => compare(compared, &request, *format, &pb),
+        (_, Provider::Ollama(config)) => run(
+            OllamaProvider::from_config(config),
+            request,
+            &args,
+            &repo,
+            forge,
+            &pb,
+        ),
+        (_, Provider::Anthropic(config)) => run(
+            AnthropicProvider::from_config(config),
+            request,
+            &args,
+            &repo,
+            forge,
+            &pb,
+        ),
+        (_, Provider::OpenAI(config)) => run(
+            OpenAIProvider::from_config(config),
+            request,
+            &args,
+            &repo,
+            forge,
+            &pb,
+        ),
+        (_, Provider::Google(config)) => run(
+            GoogleProvider::from_config(config),
+            request,
+            &args,
+            &repo,
+            forge,
+            &pb,
+        ),
+        (_, Provider::Fallback(config)) => run(
+            FallbackProvider::from_config(config),
+            request,
+            &args,
+            &repo,
+            forge,
+            &pb,
+        ),
+    };
+
+    // Tokens are spent even when the run fails
+    if args.show_usage {
+        let spends: Vec<Spend> = meter
+            .totals()
+            .into_iter()
+            .map(|(name, usage)| {
+                let name = name.unwrap_or_else(|| profile.name.clone());
+                Spend {
+                    price: settings.get(Some(name.clone())).ok().and_then(|p| p.price),
+                    profile: name,
+                    usage,
+                }
+            })
+            .collect();
+        eprint!("{}", cost::report(&spends));
+    }
+    let result = result?;
+
+    if let Some(path) = &args.output_file {
+        std::fs::write(path, format!("{}\n", result.trim_end()))?;
+    }
+    if let Some(path) = ci.as_ref().and_then(|ci| ci.summary.as_deref()) {
+        ci::write_summary(path, &result, args.language())?;
+    }
+
+    Ok(())
+}
+
+fn run<P: _Provider>(
+    provider: P,
+    request: Request,
+    args: &Args,
+    repo: &Repository,
+    forge: Option<Box<dyn Forge>>,
+    pb: &ProgressBar,
+) -> Result<String> {
+    match &args.command {
+        Some(Command::Pr { action }) => {
+            let forge = forge.ok_or(anyhow::anyhow!("No forge to publish to for `prai pr`"))?;
+            pull_request(provider, request, repo, forge.as_ref(), action, pb)
+        }
+        Some(Command::Release {
+            to,
+            version,
+            changelog,
+            ..
+        }) => release(
+            provider,
+            request,
+            repo,
+            prai::changelog::version(version.as_deref(), to),
+            changelog.as_deref(),
+            pb,
+        ),
+        Some(Command::Review { format, .. }) => review(provider, request, *format, pb),
+        _ if args.combined => combined(provider, request, args.body_file.as_deref(), pb),
+        _ if request.output == Output::Structured => structured(provider, request, pb),
+        _ => generate(provider, request, args.stream, pb),
+    }
+}
+
+fn combined<P: _Provider>(
+    provider: P,
+    request: Request,
+    body_file: Option<&Path>,
+    pb: &ProgressBar,
+) -> Result<String> {
+    let pull_request = provider.make_structured_request(request)?.pull_request;
+    pb.finish_and_clear();
+
+    let (text, body) = pull_request.to_combined(body_file.is_some());
+    if let (Some(path), Some(body)) = (body_file, body) {
+        std::fs::write(path, body)?;
+    }
+    println!("{text}");
+
+    Ok(pull_request.to_text())
+}
+
+fn pull_request<P: _Provider>(
+    provider: P,
+    request: Request,
+    repo: &Repository,
+    forge: &dyn Forge,
+    action: &PrAction,
+    pb: &ProgressBar,
+) -> Result<String> {
+    let head = forge::current_branch(repo.inner())?;
+    let base = forge::branch_name(&request.base).to_string();
+
+    match action {
+        PrAction::Create { draft, .. } => {
+            let generated = provider.make_structured_request(request)?.pull_request;
+            let pull_request = forge.create_pull_request(&NewPullRequest {
+                title: generated.title.trim(),
+                body: generated.description.trim(),
+                head: &head,
+                base: &base,
+                draft: *draft,
+            })?;
+            pb.finish_and_clear();
+            println!("Created {}", pull_request.html_url);
+            Ok(format!(
+                "{}\n\n{}",
+                pull_request.html_url,
+                generated.description.trim()
+            ))
+        }
+        PrAction::Update { .. } => {
+            let existing = forge.find_pull_request(&head)?.ok_or(anyhow::anyhow!(
+                "There is no open pull request for `{head}`. Open one with `prai pr create`"
+            ))?;
+            let generated = provider.make_structured_request(request)?.pull_request;
+            let pull_request =
+                forge.update_pull_request(existing.number, generated.description.trim())?;
+            pb.finish_and_clear();
+            println!("Updated {}", pull_request.html_url);
+            Ok(format!(
+                "{}\n\n{}",
+                pull_request.html_url,
+                generated.description.trim()
+            ))
+        }
+    }
+}
+
+fn structured<P: _Provider>(provider: P, request: Request, pb: &ProgressBar) -> Result<String> {
+    let structured = provider.make_structured_request(request)?;
+    pb.finish_and_clear();
+
+    let json = serde_json::to_string_pretty(&structured)?;
+    println!("{json}");
+    Ok(json)
+}
+
+fn review<P: _Provider>(
+    provider: P,
+    request: Request,
+    format: ReviewFormat,
+    pb: &ProgressBar,
+) -> Result<String> {
+    let response = provider.make_request(request)?;
+    pb.finish_and_clear();
+
+    let review = Review::parse(&response.text)?;
+    let findings = match format {
+        ReviewFormat::Text => review.to_text(),
+        ReviewFormat::Json => format!("{}\n", review.to_json()?),
+        ReviewFormat::Sarif => format!("{}\n", review.to_sarif()?),
+    };
+    print!("{findings}");
+
+    Ok(findings)
+}
+
+fn release<P: _Provider>(
+    provider: P,
+    request: Request,
+    repo: &Repository,
+    version: &str,
+    changelog: Option<&Path>,
+    pb: &ProgressBar,
+) -> Result<String> {
+    let released = repo.inner().find_commit(repo.resolve(&request.head)?)?;
+    let date = prai::changelog::date(released.time().seconds());
+    let notes = provider.make_request(request)?;
+    pb.finish_and_clear();
+
+    let section = prai::changelog::section(version, &date, &notes.text);
+    match changelog {
+        Some(path) => {
+            prai::changelog::write(path, &section)?;
+            println!("Updated {}", path.display());
+        }
+        None => print!("{section}"),
+    }
+
+    Ok(section)
+}
+
+/// Render the prompt once and send it to every profile at the same time
+fn compare(
+    profiles: Vec<Profile>,
+    request: &Request,
+    format: Format,
+    pb: &ProgressBar,
+) -> Result<String> {
+    let prompt = Prompt::render(request)?;
+    let comparisons: Vec<Comparison> = std::thread::scope(|scope| {
+        let handles: Vec<_> = profiles
+            .into_iter()
+            .map(|profile| scope.spawn(|| compare_profile(profile, request, &prompt)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("comparison thread panicked"))
+            .collect()
+    });
+    pb.finish_and_clear();
+
+    let text = match format {
+        Format::Text => {
+            let width = console::Term::stdout()
+                .size_checked()
+                .map_or(COMPARE_WIDTH, |(_, columns)| columns as usize);
+            compare::to_text(&comparisons, width)
         }
-        Provider::Anthropic(config) => {
-            let provider = AnthropicProvider::from_config(config);
-            provider.make_request(request)
+        Format::Json => format!("{}\n", compare::to_json(&comparisons)?),
+    };
+    print!("{text}");
+
+    Ok(text)
+}
+
+fn compare_profile(profile: Profile, request: &Request, prompt: &str) -> Comparison {
+    let mut request = request.clone();
+    request.retry = profile.retry.clone();
+    request.max_continuations = profile.max_continuations;
+    request.meter = request.meter.for_profile(&profile.name);
+
+    let name = &profile.name;
+    match profile.provider {
+        Provider::Ollama(config) => {
+            timed(name, OllamaProvider::from_config(config), &request, prompt)
         }
+        Provider::Anthropic(config) => timed(
+            name,
+            AnthropicProvider::from_config(config),
+            &request,
+            prompt,
+        ),
         Provider::OpenAI(config) => {
-            let provider = OpenAIProvider::from_config(config);
-            provider.make_request(request)
+            timed(name, OpenAIProvider::from_config(config), &request, prompt)
         }
         Provider::Google(config) => {
-            let provider = GoogleProvider::from_config(config);
-            provider.make_request(request)
+            timed(name, GoogleProvider::from_config(config), &request, prompt)
         }
-    }?;
-    pb.finish_and_clear();
-    println!("{description}");
+        Provider::Fallback(config) => timed(
+            name,
+            FallbackProvider::from_config(config),
+            &request,
+            prompt,
+        ),
+    }
+}
+
+fn timed<P: _Provider>(profile: &str, provider: P, request: &Request, prompt: &str) -> Comparison {
+    let start = Instant::now();
+    let result = provider.complete(request, prompt);
+    Comparison::new(
+        profile,
+        provider.name(),
+        provider.model(),
+        start.elapsed(),
+        result,
+    )
+}
+
+fn hook(args: &Args, action: &HookAction) -> Result<()> {
+    let repo = Repository::open()?;
+
+    match action {
+        HookAction::Install {
+            conventional,
+            force,
+        } => {
+            let mut hook_args = Vec::new();
+            if let Some(profile) = args.profile()? {
+                hook_args.extend([String::from("--profile"), profile.clone()]);
+            }
+            if args.config.to_string_lossy() != default_config_string() {
+                hook_args.extend([
+                    String::from("--config"),
+                    args.config.to_string_lossy().to_string(),
+                ]);
+            }
+            if *conventional {
+                hook_args.push(String::from("--conventional"));
+            }
+
+            let path = git::hook::install(repo.inner(), &hook_args, *force)?;
+            println!("Installed {}", path.display());
+        }
+        HookAction::Uninstall => match git::hook::uninstall(repo.inner())? {
+            Some(path) => println!("Removed {}", path.display()),
+            None => println!("No prai hook is installed"),
+        },
+    }
 
     Ok(())
 }
 
+fn generate<P: _Provider>(
+    provider: P,
+    request: Request,
+    stream: bool,
+    pb: &ProgressBar,
+) -> Result<String> {
+    if !stream {
+        let description = provider.make_request(request)?;
+        pb.finish_and_clear();
+        println!("{}", description.text);
+        return Ok(description.text);
+    }
+
+    let mut stdout = std::io::stdout();
+    let description = provider.make_stream_request(request, &mut |token| {
+        if !pb.is_finished() {
+            pb.finish_and_clear();
+        }
+        let _ = write!(stdout, "{token}");
+        let _ = stdout.flush();
+    })?;
+    pb.finish_and_clear();
+    println!();
+
+    Ok(description.text)
+}
+
+/// Width of the side by side comparison when stdout is not a terminal
+const COMPARE_WIDTH: usize = 120;
+
 static PHRASES: &[&str] = &[
     "There is no spoon... only elegant code...",
     "Questioning the reality of your function names...",
@@ -1,8 +1,18 @@
+use std::time::Duration;
+
 use log::debug;
+use reqwest::StatusCode;
 use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
 
-use crate::{providers::Provider, settings::AnthropicSettings};
+use crate::{
+    providers::{
+        Completion, FinishReason, Provider, Usage,
+        error::{ProviderError, error_message},
+        structured::SCHEMA_NAME,
+    },
+    settings::AnthropicSettings,
+};
 
 pub struct AnthropicProvider {
     config: AnthropicSettings,
@@ -16,8 +26,16 @@ impl Provider for AnthropicProvider {
         Self { config }
     }
 
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
     fn build_url(&self) -> String {
-        "https://api.anthropic.com/v1/messages".to_string()
+        format!("{}/messages", self.config.base_url)
     }
 
     fn build_request_body(&self, prompt: &str) -> anyhow::Result<serde_json::Value> {
@@ -27,17 +45,131 @@ impl Provider for AnthropicProvider {
         ))?)
     }
 
-    fn parse_response(&self, response: serde_json::Value) -> anyhow::Result<String> {
+    fn build_structured_request_body(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        // Forcing a call to a single tool makes the model answer with the tool's input schema
+        let mut body = self.build_request_body(prompt)?;
+        body["tools"] = serde_json::json!([{
+            "name": SCHEMA_NAME,
+            "description": "Record the generated pull request",
+            "input_schema": schema
+        }]);
+        body["tool_choice"] = serde_json::json!({ "type": "tool", "name": SCHEMA_NAME });
+        Ok(body)
+    }
+
+    fn parse_response(&self, response: serde_json::Value) -> anyhow::Result<Completion> {
         let generated_text = response
             .get("content")
             .and_then(|content| content.as_array())
-            .and_then(|content_array| content_array.first())
-            .and_then(|first_content| first_content.get("text"))
-            .and_then(|text| text.as_str())
-            .unwrap_or("")
-            .to_string();
+            .map(|content_array| {
+                content_array
+                    .iter()
+                    .filter_map(
+                        |content| match content.get("type").and_then(|t| t.as_str()) {
+                            Some("tool_use") => content.get("input").map(|input| input.to_string()),
+                            _ => content.get("text")?.as_str().map(String::from),
+                        },
+                    )
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+
+        let usage = response.get("usage").and_then(|usage| {
+            Some(Usage {
+                input_tokens: usage.get("input_tokens")?.as_u64()?,
+                output_tokens: usage.get("output_tokens")?.as_u64()?,
+            })
+        });
+
+        Ok(Completion {
+            text: generated_text,
+            finish_reason: finish_reason(response.get("stop_reason")),
+            usage,
+        })
+    }
+
+    fn build_continuation_body(
+        &self,
+        prompt: &str,
+        partial: &str,
+    ) -> anyhow::Result<Option<serde_json::Value>> {
+        let mut payload =
+            Payload::from_settings_and_prompt(prompt.to_string(), self.config.clone());
+        // Prefilling the assistant turn makes the model continue from the partial response.
+        // Anthropic rejects a final assistant turn ending in whitespace.
+        payload.messages.push(Message {
+            role: Role::Assistant,
+            content: partial.trim_end().to_string(),
+        });
 
-        Ok(generated_text)
+        Ok(Some(serde_json::to_value(payload)?))
+    }
+
+    fn parse_stream_event(&self, event: serde_json::Value) -> anyhow::Result<Completion> {
+        match event.get("type").and_then(|t| t.as_str()) {
+            Some("content_block_delta") => Ok(Completion {
+                text: event
+                    .get("delta")
+                    .and_then(|delta| delta.get("text"))
+                    .and_then(|text| text.as_str())
+                    .unwrap_or("")
+                    .to_string(),
+                ..Default::default()
+            }),
+            // The input tokens arrive with the first event, the output tokens with the last
+            Some("message_start") => Ok(Completion {
+                usage: stream_usage(event.pointer("/message/usage")),
+                ..Default::default()
+            }),
+            Some("message_delta") => Ok(Completion {
+                finish_reason: finish_reason(
+                    event
+                        .get("delta")
+                        .and_then(|delta| delta.get("stop_reason")),
+                ),
+                usage: stream_usage(event.get("usage")),
+                ..Default::default()
+            }),
+            Some("error") => Err(self
+                .parse_error(StatusCode::INTERNAL_SERVER_ERROR, &event.to_string(), None)
+                .into()),
+            _ => Ok(Completion::default()),
+        }
+    }
+
+    fn parse_error(
+        &self,
+        status: StatusCode,
+        body: &str,
+        retry_after: Option<Duration>,
+    ) -> ProviderError {
+        let json: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+        let message = error_message(&json, body);
+        let kind = json
+            .get("error")
+            .and_then(|error| error.get("type"))
+            .and_then(|kind| kind.as_str());
+
+        match kind {
+            Some("rate_limit_error") => ProviderError::RateLimited {
+                message,
+                retry_after,
+            },
+            Some("authentication_error" | "permission_error") => ProviderError::Auth { message },
+            Some("overloaded_error" | "api_error") => ProviderError::Server {
+                status,
+                message,
+                retry_after,
+            },
+            Some("invalid_request_error") if message.contains("prompt is too long") => {
+                ProviderError::ContextLengthExceeded { message }
+            }
+            _ => ProviderError::from_status(status, message, retry_after),
+        }
     }
 
     fn get_client(&self) -> reqwest::blocking::Client {
@@ -65,6 +197,26 @@ impl Provider for AnthropicProvider {
     }
 }
 
+/// Streamed events carry running totals, each with only some of the counts
+fn stream_usage(usage: Option<&serde_json::Value>) -> Option<Usage> {
+    let usage = usage?;
+    let count = |name: &str| usage.get(name).and_then(|count| count.as_u64());
+    Some(Usage {
+        input_tokens: count("input_tokens").unwrap_or_default(),
+        output_tokens: count("output_tokens").unwrap_or_default(),
+    })
+}
+
+fn finish_reason(stop_reason: Option<&serde_json::Value>) -> FinishReason {
+    match stop_reason.and_then(|reason| reason.as_str()) {
+        Some("end_turn" | "stop_sequence" | "tool_use") => FinishReason::Stop,
+        Some("max_tokens") => FinishReason::MaxTokens,
+        Some("refusal") => FinishReason::ContentFilter(String::from("refusal")),
+        Some(reason) => FinishReason::Other(reason.to_string()),
+        None => FinishReason::Unknown,
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Payload {
     pub model: String,
@@ -101,4 +253,66 @@ pub struct Message {
 #[serde(rename_all = "lowercase")]
 pub enum Role {
     User,
+    Assistant,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::providers::structured;
+
+    #[test]
+    fn test_structured_output_uses_forced_tool() {
+        let provider = AnthropicProvider::from_config(AnthropicSettings {
+            version: String::from("2023-06-01"),
+            model: String::from("claude-sonnet-4-0"),
+            api_key: "key".into(),
+            base_url: String::from("http://localhost"),
+            max_tokens: 500,
+            temperature: None,
+            top_p: None,
+        });
+
+        let body = provider
+            .build_structured_request_body("prompt", &structured::schema())
+            .unwrap();
+        assert_eq!(
+            serde_json::json!({ "type": "tool", "name": "pull_request" }),
+            body["tool_choice"]
+        );
+        assert_eq!(structured::schema(), body["tools"][0]["input_schema"]);
+
+        let completion = provider
+            .parse_response(serde_json::json!({
+                "content": [{
+                    "type": "tool_use",
+                    "name": "pull_request",
+                    "input": {
+                        "title": "Add JSON output",
+                        "description": "Adds `--format json`.",
+                        "type_of_change": "feature",
+                        "breaking_changes": []
+                    }
+                }],
+                "stop_reason": "tool_use",
+                "usage": { "input_tokens": 1200, "output_tokens": 80 }
+            }))
+            .unwrap();
+
+        assert_eq!(FinishReason::Stop, completion.finish_reason);
+        assert_eq!(
+            Some(Usage {
+                input_tokens: 1200,
+                output_tokens: 80
+            }),
+            completion.usage
+        );
+        assert_eq!(
+            "Add JSON output",
+            structured::parse(&completion.text).unwrap().title
+        );
+    }
 }
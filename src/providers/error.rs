@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use reqwest::{StatusCode, header::HeaderMap};
+
+use crate::providers::FinishReason;
+
+/// Typed failure returned by a provider API call
+#[derive(Debug, thiserror::Error)]
+pub enum ProviderError {
+    #[error("Rate limited by provider: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    #[error("Authentication failed: {message}")]
+    Auth { message: String },
+    #[error("Context length exceeded: {message}")]
+    ContextLengthExceeded { message: String },
+    #[error("Provider server error ({status}): {message}")]
+    Server {
+        status: StatusCode,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    #[error("API request failed with status {status}: {message}")]
+    Request { status: StatusCode, message: String },
+    #[error("Provider returned an empty response (finish reason: {0})")]
+    EmptyResponse(FinishReason),
+    #[error("HTTP transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+}
+
+impl ProviderError {
+    /// Classify a failure using only the HTTP status code
+    pub fn from_status(status: StatusCode, message: String, retry_after: Option<Duration>) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Auth { message },
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited {
+                message,
+                retry_after,
+            },
+            StatusCode::PAYLOAD_TOO_LARGE => Self::ContextLengthExceeded { message },
+            status if status.is_server_error() => Self::Server {
+                status,
+                message,
+                retry_after,
+            },
+            status => Self::Request { status, message },
+        }
+    }
+
+    /// Whether repeating the same request could reasonably succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::RateLimited { .. } | Self::Server { .. } => true,
+            Self::Transport(e) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// Delay requested by the provider before retrying, if any
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after, .. } | Self::Server { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Read the `retry-after-ms` or `retry-after` (in seconds) response headers
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|v| v.is_finite() && *v >= 0.0)
+    };
+
+    header("retry-after-ms")
+        .map(|ms| Duration::from_secs_f64(ms / 1000.0))
+        .or_else(|| header("retry-after").map(Duration::from_secs_f64))
+}
+
+/// Extract a human readable message from an error body, falling back to the raw body
+pub fn error_message(body: &serde_json::Value, raw: &str) -> String {
+    body.get("error")
+        .and_then(|error| error.get("message").or(Some(error)))
+        .and_then(|message| message.as_str())
+        .unwrap_or(raw)
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_from_status() {
+        let classify = |status| ProviderError::from_status(status, String::new(), None);
+
+        assert!(matches!(
+            classify(StatusCode::UNAUTHORIZED),
+            ProviderError::Auth { .. }
+        ));
+        assert!(matches!(
+            classify(StatusCode::TOO_MANY_REQUESTS),
+            ProviderError::RateLimited { .. }
+        ));
+        assert!(classify(StatusCode::from_u16(529).unwrap()).is_retryable());
+        assert!(!classify(StatusCode::BAD_REQUEST).is_retryable());
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, parse_retry_after(&headers));
+
+        headers.insert("retry-after", HeaderValue::from_static("2"));
+        assert_eq!(Some(Duration::from_secs(2)), parse_retry_after(&headers));
+
+        headers.insert("retry-after-ms", HeaderValue::from_static("250"));
+        assert_eq!(
+            Some(Duration::from_millis(250)),
+            parse_retry_after(&headers)
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::cell::Cell;
+
+use anyhow::{Result, anyhow};
+use log::{debug, info, warn};
+
+use crate::{
+    providers::{
+        Completion, Provider, Request, anthropic::AnthropicProvider, error::ProviderError,
+        google::GoogleProvider, ollama::OllamaProvider, openai::OpenAIProvider,
+        structured::Structured,
+    },
+    settings::{self, FallbackSettings, RetrySettings},
+};
+
+/// The parts of [`Provider`] a fallback chain needs, without `Config` so that providers
+/// of different types can share a `Vec`
+trait Link: Sync {
+    fn name(&self) -> &'static str;
+    fn model(&self) -> &str;
+    fn build_url(&self) -> String;
+    fn build_request_body(&self, prompt: &str) -> Result<serde_json::Value>;
+    fn build_structured_request_body(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> Result<serde_json::Value>;
+    fn parse_response(&self, response: serde_json::Value) -> Result<Completion>;
+    fn parse_stream_event(&self, event: serde_json::Value) -> Result<Completion>;
+    fn complete(&self, request: &Request, prompt: &str) -> Result<Completion>;
+    fn stream_prompt(
+        &self,
+        request: &Request,
+        prompt: &str,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<Completion>;
+    fn complete_structured(&self, request: &Request, prompt: &str) -> Result<Structured>;
+}
+
+impl<P: Provider> Link for P {
+    fn name(&self) -> &'static str {
+        Provider::name(self)
+    }
+    fn model(&self) -> &str {
+        Provider::model(self)
+    }
+    fn build_url(&self) -> String {
+        Provider::build_url(self)
+    }
+    fn build_request_body(&self, prompt: &str) -> Result<serde_json::Value> {
+        Provider::build_request_body(self, prompt)
+    }
+    fn build_structured_request_body(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        Provider::build_structured_request_body(self, prompt, schema)
+    }
+    fn parse_response(&self, response: serde_json::Value) -> Result<Completion> {
+        Provider::parse_response(self, response)
+    }
+    fn parse_stream_event(&self, event: serde_json::Value) -> Result<Completion> {
+        Provider::parse_stream_event(self, event)
+    }
+    fn complete(&self, request: &Request, prompt: &str) -> Result<Completion> {
+        Provider::complete(self, request, prompt)
+    }
+    fn stream_prompt(
+        &self,
+        request: &Request,
+        prompt: &str,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<Completion> {
+        Provider::stream_prompt(self, request, prompt, on_token)
+    }
+    fn complete_structured(&self, request: &Request, prompt: &str) -> Result<Structured> {
+        Provider::complete_structured(self, request, prompt)
+    }
+}
+
+/// A profile in the chain, with the settings it sends requests with
+struct Member {
+    profile: String,
+    provider: Box<dyn Link>,
+    retry: RetrySettings,
+    max_continuations: u32,
+}
+
+/// Sends each prompt to the profiles of a fallback profile in order, moving on to the
+/// next one when a profile fails with an error that retrying could fix
+pub struct FallbackProvider {
+    chain: Vec<Member>,
+}
+
+impl FallbackProvider {
+    fn first(&self) -> &dyn Link {
+        self.chain[0].provider.as_ref()
+    }
+
+    /// Run `f` against each member until one succeeds or fails with an error that another
+    /// profile would not fix
+    fn attempt<T>(
+        &self,
+        request: &Request,
+        f: impl FnMut(&dyn Link, &Request) -> Result<T>,
+    ) -> Result<T> {
+        self.attempt_while(request, || true, f)
+    }
+
+    /// Like [`FallbackProvider::attempt`], but only moves on to the next member while
+    /// `can_fall_back` holds
+    fn attempt_while<T>(
+        &self,
+        request: &Request,
+        can_fall_back: impl Fn() -> bool,
+        mut f: impl FnMut(&dyn Link, &Request) -> Result<T>,
+    ) -> Result<T> {
+        let mut last = None;
+        for (index, member) in self.chain.iter().enumerate() {
+            let mut request = request.clone();
+            request.retry = member.retry.clone();
+            request.max_continuations = member.max_continuations;
+            request.meter = request.meter.for_profile(&member.profile);
+
+            match f(member.provider.as_ref(), &request) {
+                Ok(result) => {
+                    let message = format!(
+                        "Generated with profile `{}` ({} {})",
+                        member.profile,
+                        member.provider.name(),
+                        member.provider.model()
+                    );
+                    if index == 0 {
+                        info!("{message}");
+                    } else {
+                        warn!("{message}");
+                    }
+                    return Ok(result);
+                }
+                Err(e) if is_retryable(&e) && can_fall_back() => {
+                    warn!("Profile `{}` failed: {e:#}", member.profile);
+                    last = Some(e);
+                }
+                Err(e) => {
+                    return Err(e.context(format!("Profile `{}` failed", member.profile)));
+                }
+            }
+        }
+
+        Err(last
+            .unwrap_or(anyhow!("The fallback chain is empty"))
+            .context("Every profile in the fallback chain failed"))
+    }
+}
+
+fn is_retryable(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ProviderError>()
+        .is_some_and(ProviderError::is_retryable)
+}
+
+impl Provider for FallbackProvider {
+    type Config = FallbackSettings;
+    fn from_config(config: Self::Config) -> Self {
+        debug!("Create fallback chain from {:?}", config.profiles);
+        let chain = config
+            .chain
+            .into_iter()
+            .map(|profile| {
+                let provider: Box<dyn Link> = match profile.provider {
+                    settings::Provider::Anthropic(config) => {
+                        Box::new(AnthropicProvider::from_config(config))
+                    }
+                    settings::Provider::Ollama(config) => {
+                        Box::new(OllamaProvider::from_config(config))
+                    }
+                    settings::Provider::OpenAI(config) => {
+                        Box::new(OpenAIProvider::from_config(config))
+                    }
+                    settings::Provider::Google(config) => {
+                        Box::new(GoogleProvider::from_config(config))
+                    }
+                    settings::Provider::Fallback(_) => {
+                        unreachable!("nested fallback profiles are rejected by Settings::get")
+                    }
+                };
+                Member {
+                    profile: profile.name,
+                    provider,
+                    retry: profile.retry,
+                    max_continuations: profile.max_continuations,
+                }
+            })
+            .collect();
+        Self { chain }
+    }
+    fn name(&self) -> &'static str {
+        "fallback"
+    }
+
+    fn model(&self) -> &str {
+        self.first().model()
+    }
+
+    fn build_url(&self) -> String {
+        self.first().build_url()
+    }
+
+    fn build_request_body(&self, prompt: &str) -> Result<serde_json::Value> {
+        self.first().build_request_body(prompt)
+    }
+
+    fn parse_response(&self, response: serde_json::Value) -> Result<Completion> {
+        self.first().parse_response(response)
+    }
+
+    fn build_structured_request_body(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        self.first().build_structured_request_body(prompt, schema)
+    }
+
+    fn parse_stream_event(&self, event: serde_json::Value) -> Result<Completion> {
+        self.first().parse_stream_event(event)
+    }
+
+    fn complete(&self, request: &Request, prompt: &str) -> Result<Completion> {
+        self.attempt(request, |provider, request| {
+            provider.complete(request, prompt)
+        })
+    }
+
+    fn stream_prompt(
+        &self,
+        request: &Request,
+        prompt: &str,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<Completion> {
+        // Once tokens were printed another profile would repeat them, so only fall back
+        // while nothing has been streamed
+        let streamed = Cell::new(false);
+        self.attempt_while(
+            request,
+            || !streamed.get(),
+            |provider, request| {
+                provider.stream_prompt(request, prompt, &mut |token| {
+                    streamed.set(true);
+                    on_token(token)
+                })
+            },
+        )
+    }
+
+    fn complete_structured(&self, request: &Request, prompt: &str) -> Result<Structured> {
+        self.attempt(request, |provider, request| {
+            provider.complete_structured(request, prompt)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indoc::formatdoc;
+    use pretty_assertions::assert_eq;
+
+    use crate::settings::Settings;
+
+    fn provider(primary: &mockito::Server, secondary: &mockito::Server) -> FallbackProvider {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            formatdoc! {r#"
+                default = "chain"
+
+                [[profile]]
+                name = "chain"
+                provider = "fallback"
+                profiles = ["primary", "secondary"]
+
+                [[profile]]
+                name = "primary"
+                provider = "ollama"
+                url = "{}"
+                model = "codegemma:7b"
+                retry = {{ max_attempts = 1 }}
+
+                [[profile]]
+                name = "secondary"
+                provider = "ollama"
+                url = "{}"
+                model = "llama3:8b"
+            "#, primary.url(), secondary.url()},
+        )
+        .unwrap();
+
+        let profile = Settings::from_path(&path).unwrap().get(None).unwrap();
+        let settings::Provider::Fallback(config) = profile.provider else {
+            panic!("expected a fallback profile");
+        };
+        FallbackProvider::from_config(config)
+    }
+
+    fn request() -> Request {
+        Request::builder()
+            .base(String::from("main"))
+            .head(String::from("HEAD"))
+            .exclude(vec![])
+            .build()
+    }
+
+    #[test]
+    fn test_falls_back_on_retryable_errors() {
+        let mut primary = mockito::Server::new();
+        let mut secondary = mockito::Server::new();
+        let overloaded = primary
+            .mock("POST", "/api/generate")
+            .with_status(503)
+            .with_body(r#"{"error": "overloaded"}"#)
+            .expect(1)
+            .create();
+        secondary
+            .mock("POST", "/api/generate")
+            .with_body(r#"{"response": "Add fallback profiles", "done_reason": "stop"}"#)
+            .create();
+
+        let provider = provider(&primary, &secondary);
+        let completion = Provider::complete(&provider, &request(), "Describe the change").unwrap();
+
+        assert_eq!("Add fallback profiles", completion.text);
+        overloaded.assert();
+    }
+
+    #[test]
+    fn test_stops_on_fatal_errors() {
+        let mut primary = mockito::Server::new();
+        let mut secondary = mockito::Server::new();
+        primary
+            .mock("POST", "/api/generate")
+            .with_status(400)
+            .with_body(r#"{"error": "model not found"}"#)
+            .create();
+        let unused = secondary.mock("POST", "/api/generate").expect(0).create();
+
+        let provider = provider(&primary, &secondary);
+        let error = Provider::complete(&provider, &request(), "Describe the change").unwrap_err();
+
+        assert!(format!("{error:#}").starts_with("Profile `primary` failed"));
+        unused.assert();
+    }
+}
@@ -1,7 +1,17 @@
+use std::time::Duration;
+
 use log::debug;
+use reqwest::StatusCode;
 use secrecy::ExposeSecret;
 
-use crate::{providers::Provider, settings::GoogleSettings};
+use crate::{
+    providers::{
+        Completion, FinishReason, Provider, Request, Usage,
+        error::{ProviderError, error_message},
+        structured,
+    },
+    settings::GoogleSettings,
+};
 
 pub struct GoogleProvider {
     config: GoogleSettings,
@@ -15,6 +25,14 @@ impl Provider for GoogleProvider {
         Self { config }
     }
 
+    fn name(&self) -> &'static str {
+        "google"
+    }
+
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
     fn build_url(&self) -> String {
         format!(
             "{}/models/{}:generateContent?key={}",
@@ -43,21 +61,106 @@ impl Provider for GoogleProvider {
         }))
     }
 
-    fn parse_response(&self, response: serde_json::Value) -> anyhow::Result<String> {
-        let generated_text = response
-            .get("candidates")
-            .and_then(|candidates| candidates.as_array())
-            .and_then(|candidates| candidates.first())
-            .and_then(|candidate| candidate.get("content"))
-            .and_then(|content| content.get("parts"))
-            .and_then(|parts| parts.as_array())
-            .and_then(|parts| parts.first())
-            .and_then(|part| part.get("text"))
-            .and_then(|text| text.as_str())
-            .unwrap_or("")
-            .to_string();
-
-        Ok(generated_text)
+    fn build_structured_request_body(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let mut body = self.build_request_body(prompt)?;
+        body["generationConfig"]["responseMimeType"] = "application/json".into();
+        body["generationConfig"]["responseSchema"] = structured::to_openapi(schema);
+        Ok(body)
+    }
+
+    fn parse_response(&self, response: serde_json::Value) -> anyhow::Result<Completion> {
+        Ok(Completion {
+            text: candidate_text(&response).unwrap_or("").to_string(),
+            finish_reason: finish_reason(&response),
+            usage: usage(&response),
+        })
+    }
+
+    fn build_continuation_body(
+        &self,
+        prompt: &str,
+        partial: &str,
+    ) -> anyhow::Result<Option<serde_json::Value>> {
+        let mut body = self.build_request_body(prompt)?;
+        body["contents"] = serde_json::json!([
+            {
+                "role": "user",
+                "parts": [{ "text": prompt }]
+            },
+            {
+                "role": "model",
+                "parts": [{ "text": partial }]
+            },
+            {
+                "role": "user",
+                "parts": [{ "text": "Continue exactly where you left off. Do not repeat any previous text." }]
+            }
+        ]);
+
+        Ok(Some(body))
+    }
+
+    fn build_stream_url(&self) -> String {
+        format!(
+            "{}/models/{}:streamGenerateContent?alt=sse&key={}",
+            self.config.base_url,
+            self.config.model,
+            self.config.api_key.expose_secret()
+        )
+    }
+
+    fn build_stream_request_body(
+        &self,
+        _request: &Request,
+        prompt: &str,
+    ) -> anyhow::Result<serde_json::Value> {
+        self.build_request_body(prompt)
+    }
+
+    fn parse_stream_event(&self, event: serde_json::Value) -> anyhow::Result<Completion> {
+        if let Some(error) = event.get("error") {
+            return Err(anyhow::anyhow!("Stream failed: {error}"));
+        }
+
+        self.parse_response(event)
+    }
+
+    fn parse_error(
+        &self,
+        status: StatusCode,
+        body: &str,
+        retry_after: Option<Duration>,
+    ) -> ProviderError {
+        let json: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+        let message = error_message(&json, body);
+        let kind = json
+            .get("error")
+            .and_then(|error| error.get("status"))
+            .and_then(|kind| kind.as_str());
+
+        match kind {
+            Some("RESOURCE_EXHAUSTED") => ProviderError::RateLimited {
+                message,
+                retry_after,
+            },
+            Some("UNAUTHENTICATED" | "PERMISSION_DENIED") => ProviderError::Auth { message },
+            Some("INVALID_ARGUMENT") if message.contains("API key") => {
+                ProviderError::Auth { message }
+            }
+            Some("INVALID_ARGUMENT") if message.contains("token count") => {
+                ProviderError::ContextLengthExceeded { message }
+            }
+            Some("UNAVAILABLE" | "INTERNAL" | "DEADLINE_EXCEEDED") => ProviderError::Server {
+                status,
+                message,
+                retry_after,
+            },
+            _ => ProviderError::from_status(status, message, retry_after),
+        }
     }
 
     fn get_client(&self) -> reqwest::blocking::Client {
@@ -74,3 +177,88 @@ impl Provider for GoogleProvider {
             .unwrap_or_else(|_| reqwest::blocking::Client::new())
     }
 }
+
+fn candidate_text(response: &serde_json::Value) -> Option<&str> {
+    response
+        .get("candidates")
+        .and_then(|candidates| candidates.as_array())
+        .and_then(|candidates| candidates.first())
+        .and_then(|candidate| candidate.get("content"))
+        .and_then(|content| content.get("parts"))
+        .and_then(|parts| parts.as_array())
+        .and_then(|parts| parts.first())
+        .and_then(|part| part.get("text"))
+        .and_then(|text| text.as_str())
+}
+
+fn usage(response: &serde_json::Value) -> Option<Usage> {
+    let usage = response.get("usageMetadata")?;
+    Some(Usage {
+        input_tokens: usage.get("promptTokenCount")?.as_u64()?,
+        output_tokens: usage
+            .get("candidatesTokenCount")
+            .and_then(|count| count.as_u64())
+            .unwrap_or_default(),
+    })
+}
+
+fn finish_reason(response: &serde_json::Value) -> FinishReason {
+    let block_reason = response
+        .get("promptFeedback")
+        .and_then(|feedback| feedback.get("blockReason"))
+        .and_then(|reason| reason.as_str());
+    let finish_reason = response
+        .get("candidates")
+        .and_then(|candidates| candidates.as_array())
+        .and_then(|candidates| candidates.first())
+        .and_then(|candidate| candidate.get("finishReason"))
+        .and_then(|reason| reason.as_str());
+
+    match (block_reason, finish_reason) {
+        (Some(reason), _) => FinishReason::ContentFilter(reason.to_string()),
+        (None, Some("STOP")) => FinishReason::Stop,
+        (None, Some("MAX_TOKENS")) => FinishReason::MaxTokens,
+        (
+            None,
+            Some(reason @ ("SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII")),
+        ) => FinishReason::ContentFilter(reason.to_string()),
+        (None, Some(reason)) => FinishReason::Other(reason.to_string()),
+        (None, None) => FinishReason::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::providers::error::ProviderError;
+
+    #[test]
+    fn test_blocked_prompt_fails() {
+        let provider = GoogleProvider::from_config(GoogleSettings {
+            model: String::from("gemini-pro"),
+            api_key: "key".into(),
+            base_url: String::from("http://localhost"),
+            max_tokens: 500,
+            temperature: 0.3,
+            top_p: 0.9,
+        });
+
+        let completion = provider
+            .parse_response(serde_json::json!({
+                "promptFeedback": { "blockReason": "SAFETY" }
+            }))
+            .unwrap();
+
+        assert_eq!(
+            FinishReason::ContentFilter(String::from("SAFETY")),
+            completion.finish_reason
+        );
+        assert!(matches!(
+            completion.validate(),
+            Err(ProviderError::EmptyResponse(FinishReason::ContentFilter(_)))
+        ));
+    }
+}
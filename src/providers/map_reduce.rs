@@ -0,0 +1,247 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{Result, anyhow};
+use log::info;
+
+use crate::{
+    budget,
+    git::Diff,
+    providers::{Provider, Request, prompt::Prompt},
+    settings::{ChunkBy, MapReduceSettings},
+};
+
+/// A group of file diffs summarized together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub label: String,
+    pub diff: String,
+}
+
+/// Summarize each chunk of the diff and render a prompt combining the summaries
+pub fn render<P: Provider + ?Sized>(
+    provider: &P,
+    request: &Request,
+    settings: &MapReduceSettings,
+) -> Result<String> {
+    let diff = Prompt::diff(request)?;
+    let chunks = split(&diff, settings.chunk_by, request.max_prompt_tokens);
+    info!(
+        "Summarizing {} chunk(s) with parallelism {}",
+        chunks.len(),
+        settings.parallelism
+    );
+
+    let summaries = summarize(provider, request, &chunks, settings.parallelism)?;
+
+    Prompt::render_summaries(
+        request,
+        &chunks
+            .into_iter()
+            .map(|c| c.label)
+            .zip(summaries)
+            .collect::<Vec<_>>(),
+        &diff.skipped,
+    )
+}
+
+/// Group a diff into chunks by file or by parent directory. Chunks over the token
+/// budget are cut short so each chunk request fits the model's context window.
+pub fn split(diff: &Diff, chunk_by: ChunkBy, max_tokens: Option<usize>) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    // Chunks by label, in the order labels first appear. Sorted paths do not keep a
+    // directory's files together, `a/b/c.rs` sorts between `a/b.rs` and `a/c.rs`.
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for file in &diff.files {
+        let label = match chunk_by {
+            ChunkBy::File => file.path.to_string(),
+            ChunkBy::Directory => file
+                .path
+                .rsplit_once('/')
+                .map_or(String::from("."), |(dir, _)| dir.to_string()),
+        };
+
+        match index.get(&label) {
+            Some(&i) => chunks[i].diff.push_str(&file.patch),
+            None => {
+                index.insert(label.clone(), chunks.len());
+                chunks.push(Chunk {
+                    label,
+                    diff: file.patch.clone(),
+                });
+            }
+        }
+    }
+
+    if let Some(max_tokens) = max_tokens {
+        for chunk in chunks.iter_mut() {
+            if budget::estimate_tokens(&chunk.diff) > max_tokens {
+                let end = chunk
+                    .diff
+                    .char_indices()
+                    .nth(max_tokens * 4)
+                    .map_or(chunk.diff.len(), |(i, _)| i);
+                chunk.diff.truncate(end);
+                chunk.diff.push_str("\n[TRUNCATED]\n");
+            }
+        }
+    }
+
+    chunks
+}
+
+/// Summarize every chunk, running at most `parallelism` requests at once.
+/// Summaries are returned in the same order as the chunks.
+pub fn summarize<P: Provider + ?Sized>(
+    provider: &P,
+    request: &Request,
+    chunks: &[Chunk],
+    parallelism: usize,
+) -> Result<Vec<String>> {
+    let queue = Mutex::new(chunks.iter().enumerate());
+    let workers = parallelism.clamp(1, chunks.len().max(1));
+
+    let mut results = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let Some((i, chunk)) = queue.lock().unwrap().next() else {
+                            break;
+                        };
+                        results.push((i, summarize_chunk(provider, request, chunk)));
+                    }
+                    results
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().map_err(|_| anyhow!("Summary worker panicked")))
+            .collect::<Result<Vec<_>>>()
+    })?
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, summary)| summary).collect()
+}
+
+fn summarize_chunk<P: Provider + ?Sized>(
+    provider: &P,
+    request: &Request,
+    chunk: &Chunk,
+) -> Result<String> {
+    let prompt = Prompt::render_chunk(request, &chunk.diff);
+    provider
+        .complete(request, &prompt)
+        .map(|c| c.text)
+        .map_err(|e| e.context(format!("Failed to summarize `{}`", chunk.label)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        git::{ChangeStatus, FileChange},
+        providers::ollama::OllamaProvider,
+        settings::OllamaSettings,
+    };
+
+    fn diff() -> Diff {
+        let file = |path: &str, line: &str| FileChange {
+            path: path.to_string(),
+            old_path: None,
+            status: ChangeStatus::Modified,
+            binary: false,
+            additions: 1,
+            deletions: 0,
+            hunks: Vec::new(),
+            patch: format!("diff --git a/{path} b/{path}\n+{line}\n"),
+        };
+
+        Diff {
+            files: vec![
+                file("src/main.rs", "fn main() {}"),
+                file("src/providers/mod.rs", "pub mod map_reduce;"),
+                file("src/providers/prompt.rs", "pub struct Prompt;"),
+            ],
+            skipped: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_split_by_directory() {
+        let chunks = split(&diff(), ChunkBy::Directory, None);
+
+        assert_eq!(
+            vec!["src", "src/providers"],
+            chunks.iter().map(|c| c.label.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(3, split(&diff(), ChunkBy::File, None).len());
+    }
+
+    #[test]
+    fn test_split_by_directory_groups_non_adjacent_files() {
+        let mut diff = diff();
+        diff.files[0].path = String::from("a/b.rs");
+        diff.files[1].path = String::from("a/b/c.rs");
+        diff.files[2].path = String::from("a/c.rs");
+
+        let chunks = split(&diff, ChunkBy::Directory, None);
+
+        assert_eq!(
+            vec!["a", "a/b"],
+            chunks.iter().map(|c| c.label.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            format!("{}{}", diff.files[0].patch, diff.files[2].patch),
+            chunks[0].diff
+        );
+    }
+
+    #[test]
+    fn test_summarize_preserves_chunk_order() {
+        let mut server = mockito::Server::new();
+        for (path, summary) in [
+            ("src/main.rs", "- Added main"),
+            ("src/providers/mod.rs", "- Registered module"),
+            ("src/providers/prompt.rs", "- Added prompt"),
+        ] {
+            server
+                .mock("POST", "/api/generate")
+                .match_body(mockito::Matcher::Regex(path.to_string()))
+                .with_body(
+                    serde_json::json!({ "response": summary, "done_reason": "stop" }).to_string(),
+                )
+                .create();
+        }
+
+        let provider = OllamaProvider::from_config(OllamaSettings {
+            url: server.url(),
+            model: String::from("codegemma:7b"),
+            temperature: 0.3,
+            top_p: 0.9,
+            num_predict: 500,
+        });
+        let request = Request::builder()
+            .base(String::from("main"))
+            .head(String::from("HEAD"))
+            .exclude(vec![])
+            .build();
+
+        let summaries =
+            summarize(&provider, &request, &split(&diff(), ChunkBy::File, None), 2).unwrap();
+
+        assert_eq!(
+            vec!["- Added main", "- Registered module", "- Added prompt"],
+            summaries
+        );
+    }
+}
@@ -1,41 +1,244 @@
 pub mod anthropic;
+pub mod error;
+pub mod fallback;
 pub mod google;
+pub mod map_reduce;
 pub mod ollama;
 pub mod openai;
 pub mod prompt;
+pub mod retry;
+pub mod stream;
+pub mod structured;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use anyhow::Result;
 use bon::Builder;
-use log::trace;
+use log::{trace, warn};
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use crate::{
+    git::DiffMode,
+    settings::{MapReduceSettings, RedactSettings, RetrySettings},
+};
+use error::ProviderError;
+use stream::StreamFormat;
+use structured::Structured;
 
-#[derive(Builder, Debug)]
+#[derive(Builder, Debug, Clone)]
 pub struct Request {
     pub base: String,
     pub head: String,
+    /// Describe committed changes between `base` and `head` or uncommitted work
+    #[builder(default)]
+    pub mode: DiffMode,
+    /// Diff `base` directly instead of the merge base of `base` and `head`
+    #[builder(default)]
+    pub two_dot: bool,
+    /// Include the messages of the commits between `base` and `head`
+    #[builder(default)]
+    pub commits: bool,
+    #[builder(default)]
+    pub include: Vec<String>,
     pub exclude: Vec<String>,
     pub template: Option<String>,
     pub role: Option<String>,
     pub directive: Option<String>,
-    pub is_title: bool,
+    #[builder(default)]
+    pub output: Output,
+    #[builder(default)]
+    pub retry: RetrySettings,
+    #[builder(default)]
+    pub max_continuations: u32,
+    pub max_prompt_tokens: Option<usize>,
+    /// Summarize the diff in chunks before rendering the final prompt
+    pub map_reduce: Option<MapReduceSettings>,
+    /// Scrub secrets from the diff before it is sent to the provider
+    pub redact: Option<RedactSettings>,
+    /// Where the usage of every request made for this one is added up
+    #[builder(default)]
+    pub meter: Meter,
+    /// Ask for token usage where providers only report it on request, for `--show-usage`
+    #[builder(default)]
+    pub report_usage: bool,
+}
+
+/// What the model is asked to write
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Output {
+    #[default]
+    Description,
+    Title,
+    /// A commit message, optionally following Conventional Commits
+    CommitMessage {
+        conventional: bool,
+    },
+    /// Changelog entries for the commits between two releases
+    ReleaseNotes,
+    /// Code review findings as JSON, see [`crate::review::Review`]
+    Review,
+    /// Title and description together as JSON, see [`structured::PullRequest`]
+    Structured,
+}
+
+/// Generated text along with the metadata reported by the provider
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Completion {
+    pub text: String,
+    pub finish_reason: FinishReason,
+    /// Token counts, when the provider reports them
+    pub usage: Option<Usage>,
 }
 
-pub trait Provider {
+/// Tokens consumed by a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl Usage {
+    /// Add up the usage of several requests, ignoring requests which did not report any
+    pub fn combine(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(Self {
+                input_tokens: a.input_tokens + b.input_tokens,
+                output_tokens: a.output_tokens + b.output_tokens,
+            }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Merge the running totals reported by successive events of one streamed response,
+    /// which may each carry only some of the counts
+    pub fn latest(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(Self {
+                input_tokens: a.input_tokens.max(b.input_tokens),
+                output_tokens: a.output_tokens.max(b.output_tokens),
+            }),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Usage per profile, `None` for requests made without naming one
+pub type Totals = Vec<(Option<String>, Usage)>;
+
+/// Adds up the usage of the requests made on behalf of each profile. Clones share the
+/// totals, so chunk summaries, retries for invalid JSON and fallback profiles are counted.
+#[derive(Debug, Clone, Default)]
+pub struct Meter {
+    profile: Option<String>,
+    totals: Arc<Mutex<Totals>>,
+}
+
+impl Meter {
+    pub fn new(profile: &str) -> Self {
+        Self {
+            profile: Some(profile.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// A meter adding to the same totals on behalf of another profile
+    pub fn for_profile(&self, profile: &str) -> Self {
+        Self {
+            profile: Some(profile.to_string()),
+            totals: Arc::clone(&self.totals),
+        }
+    }
+
+    pub fn record(&self, usage: Option<Usage>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        let mut totals = self.totals.lock().unwrap();
+        match totals
+            .iter_mut()
+            .find(|(profile, _)| *profile == self.profile)
+        {
+            Some((_, total)) => *total = Usage::combine(Some(*total), Some(usage)).unwrap(),
+            None => totals.push((self.profile.clone(), usage)),
+        }
+    }
+
+    /// Usage per profile, in the order the profiles were first used
+    pub fn totals(&self) -> Totals {
+        self.totals.lock().unwrap().clone()
+    }
+}
+
+impl Completion {
+    /// Fail on empty output and warn when the output was cut short
+    pub fn validate(self) -> Result<Self, ProviderError> {
+        if self.text.trim().is_empty() {
+            return Err(ProviderError::EmptyResponse(self.finish_reason));
+        }
+
+        match &self.finish_reason {
+            FinishReason::MaxTokens => warn!(
+                "Response was truncated by the token limit. Raise `max_tokens`/`num_predict` or set `max_continuations` on the profile"
+            ),
+            FinishReason::ContentFilter(reason) => {
+                warn!("Response may be incomplete, it was filtered by the provider: {reason}")
+            }
+            _ => {}
+        }
+
+        Ok(self)
+    }
+}
+
+/// Why the provider stopped generating
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FinishReason {
+    /// The model finished its response naturally
+    Stop,
+    /// The response was cut off by `max_tokens`/`num_predict`
+    MaxTokens,
+    /// The prompt or response was blocked by a safety or content filter
+    ContentFilter(String),
+    /// Any other reason reported by the provider
+    Other(String),
+    /// The provider did not report a reason
+    #[default]
+    Unknown,
+}
+
+impl std::fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stop => write!(f, "stop"),
+            Self::MaxTokens => write!(f, "max tokens"),
+            Self::ContentFilter(reason) => write!(f, "content filter ({reason})"),
+            Self::Other(reason) => write!(f, "{reason}"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+pub trait Provider: Sync {
     type Config;
 
     fn from_config(config: Self::Config) -> Self;
 
+    /// Name of the provider as written in profiles
+    fn name(&self) -> &'static str;
+
+    /// Model the requests are sent to
+    fn model(&self) -> &str;
+
     /// Build the prompt from the request parameters
     fn build_prompt(&self, request: &Request) -> Result<String> {
-        let exclusions: Vec<&str> = request.exclude.iter().map(|s| s.as_str()).collect();
-        let prompt = prompt::Prompt::render(
-            request.base.as_str(),
-            request.head.as_str(),
-            &exclusions,
-            request.role.as_deref(),
-            request.directive.as_deref(),
-            request.template.as_deref(),
-            request.is_title,
-        )?;
+        let prompt = match &request.map_reduce {
+            Some(settings) => map_reduce::render(self, request, settings)?,
+            None => prompt::Prompt::render(request)?,
+        };
         trace!("Prompt:\n{prompt}");
         Ok(prompt)
     }
@@ -46,39 +249,225 @@ pub trait Provider {
     /// Build the request body for the API call
     fn build_request_body(&self, prompt: &str) -> Result<serde_json::Value>;
 
-    /// Parse the response and extract the generated text
-    fn parse_response(&self, response: serde_json::Value) -> Result<String>;
+    /// Parse the response and extract the generated text and finish reason
+    fn parse_response(&self, response: serde_json::Value) -> Result<Completion>;
+
+    /// Build a request body constraining the response to JSON matching `schema`
+    fn build_structured_request_body(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> Result<serde_json::Value>;
+
+    /// Build a request body asking the model to pick up where a truncated response stopped.
+    /// Providers which cannot continue a response return `None`.
+    fn build_continuation_body(
+        &self,
+        _prompt: &str,
+        _partial: &str,
+    ) -> Result<Option<serde_json::Value>> {
+        Ok(None)
+    }
+
+    /// Build the streaming API endpoint URL (defaults to the regular endpoint)
+    fn build_stream_url(&self) -> String {
+        self.build_url()
+    }
+
+    /// Build the request body for a streaming API call (defaults to setting `"stream": true`)
+    fn build_stream_request_body(
+        &self,
+        _request: &Request,
+        prompt: &str,
+    ) -> Result<serde_json::Value> {
+        let mut body = self.build_request_body(prompt)?;
+        if let Some(body) = body.as_object_mut() {
+            body.insert(String::from("stream"), serde_json::Value::Bool(true));
+        }
+        Ok(body)
+    }
+
+    /// Wire format of the streamed response
+    fn stream_format(&self) -> StreamFormat {
+        StreamFormat::Sse
+    }
+
+    /// Parse a single streamed event into a partial completion holding the text delta
+    /// and the finish reason, if the event reported one
+    fn parse_stream_event(&self, event: serde_json::Value) -> Result<Completion>;
 
     /// Get the HTTP client (default implementation creates a new blocking client)
     fn get_client(&self) -> reqwest::blocking::Client {
         reqwest::blocking::Client::new()
     }
 
-    /// Make the HTTP request (default implementation)
-    fn make_http_request(&self, url: &str, body: &serde_json::Value) -> Result<serde_json::Value> {
+    /// Classify a non-2xx response from the provider's error payload
+    fn parse_error(
+        &self,
+        status: StatusCode,
+        body: &str,
+        retry_after: Option<Duration>,
+    ) -> ProviderError {
+        let json = serde_json::from_str(body).unwrap_or_default();
+        ProviderError::from_status(status, error::error_message(&json, body), retry_after)
+    }
+
+    /// Send a single request, returning the response only if it was successful
+    fn send(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<reqwest::blocking::Response, ProviderError> {
         let client = self.get_client();
-        log::debug!("{body:?}");
         let response = client.post(url).json(body).send()?;
 
         if !response.status().is_success() {
             let status = response.status();
-            log::error!("{}", response.text()?);
-            return Err(anyhow::anyhow!("API request failed with status: {status}",));
+            let retry_after = error::parse_retry_after(response.headers());
+            let text = response.text()?;
+            log::debug!("{status}: {text}");
+            return Err(self.parse_error(status, &text, retry_after));
         }
 
+        Ok(response)
+    }
+
+    /// Make the HTTP request, retrying transient failures (default implementation)
+    fn make_http_request(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+        retry: &RetrySettings,
+    ) -> Result<serde_json::Value> {
+        log::debug!("{body:?}");
+        let response = retry::with_retry(retry, || self.send(url, body))?;
+
         Ok(response.json()?)
     }
 
+    /// Send an already rendered prompt and wait for the full response
+    fn complete(&self, request: &Request, prompt: &str) -> Result<Completion> {
+        let url = self.build_url();
+        let request_body = self.build_request_body(prompt)?;
+        let response_json = self.make_http_request(&url, &request_body, &request.retry)?;
+        let mut completion = self.parse_response(response_json)?;
+
+        for attempt in 1..=request.max_continuations {
+            if completion.finish_reason != FinishReason::MaxTokens {
+                break;
+            }
+            let Some(body) = self.build_continuation_body(prompt, &completion.text)? else {
+                break;
+            };
+            warn!(
+                "Response was truncated, continuing ({attempt}/{})",
+                request.max_continuations
+            );
+            let response_json = self.make_http_request(&url, &body, &request.retry)?;
+            let continuation = self.parse_response(response_json)?;
+            completion.text = format!("{}{}", completion.text.trim_end(), continuation.text);
+            completion.finish_reason = continuation.finish_reason;
+            completion.usage = Usage::combine(completion.usage, continuation.usage);
+        }
+
+        request.meter.record(completion.usage);
+        Ok(completion.validate()?)
+    }
+
+    /// Send an already rendered prompt and hand each token to `on_token` as it arrives
+    fn stream_prompt(
+        &self,
+        request: &Request,
+        prompt: &str,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<Completion> {
+        let url = self.build_stream_url();
+        let request_body = self.build_stream_request_body(request, prompt)?;
+        log::debug!("{request_body:?}");
+        let response = retry::with_retry(&request.retry, || self.send(&url, &request_body))?;
+
+        let mut completion = Completion::default();
+        stream::for_each_event(
+            std::io::BufReader::new(response),
+            self.stream_format(),
+            |event| {
+                trace!("{event}");
+                let delta = self.parse_stream_event(event)?;
+                if !delta.text.is_empty() {
+                    on_token(&delta.text);
+                    completion.text.push_str(&delta.text);
+                }
+                if delta.finish_reason != FinishReason::Unknown {
+                    completion.finish_reason = delta.finish_reason;
+                }
+                completion.usage = Usage::latest(completion.usage, delta.usage);
+                Ok(())
+            },
+        )?;
+
+        request.meter.record(completion.usage);
+        Ok(completion.validate()?)
+    }
+
     /// Main request method with default implementation using the other trait methods
-    fn make_request(&self, request: Request) -> Result<String> {
+    fn make_request(&self, request: Request) -> Result<Completion> {
         trace!("{request:?}");
 
         let prompt = self.build_prompt(&request)?;
+        self.complete(&request, &prompt)
+    }
+
+    /// Send an already rendered prompt asking for JSON matching [`structured::schema`].
+    /// A response which does not match the schema is retried once.
+    fn complete_structured(&self, request: &Request, prompt: &str) -> Result<Structured> {
         let url = self.build_url();
-        let request_body = self.build_request_body(&prompt)?;
-        let response_json = self.make_http_request(&url, &request_body)?;
-        let generated_text = self.parse_response(response_json)?;
+        let body = self.build_structured_request_body(prompt, &structured::schema())?;
+
+        let mut usage = None;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let response_json = self.make_http_request(&url, &body, &request.retry)?;
+            let completion = self.parse_response(response_json)?;
+            request.meter.record(completion.usage);
+            let completion = completion.validate()?;
+            usage = Usage::combine(usage, completion.usage);
 
-        Ok(generated_text)
+            match structured::parse(&completion.text) {
+                Ok(pull_request) => {
+                    return Ok(Structured {
+                        pull_request,
+                        provider: self.name().to_string(),
+                        model: self.model().to_string(),
+                        usage,
+                    });
+                }
+                Err(e) if attempt < 2 => {
+                    warn!("Invalid structured response, retrying: {e:#}");
+                    log::debug!("{}", completion.text);
+                }
+                Err(e) => return Err(e.context("The provider did not return valid JSON")),
+            }
+        }
+    }
+
+    /// Ask for a pull request title and description as JSON, see [`Provider::complete_structured`]
+    fn make_structured_request(&self, request: Request) -> Result<Structured> {
+        trace!("{request:?}");
+
+        let prompt = self.build_prompt(&request)?;
+        self.complete_structured(&request, &prompt)
+    }
+
+    /// Streaming variant of [`Provider::make_request`]
+    fn make_stream_request(
+        &self,
+        request: Request,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<Completion> {
+        trace!("{request:?}");
+
+        let prompt = self.build_prompt(&request)?;
+        self.stream_prompt(&request, &prompt, on_token)
     }
 }
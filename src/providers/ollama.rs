@@ -1,6 +1,9 @@
 use log::debug;
 
-use crate::{providers::Provider, settings::OllamaSettings};
+use crate::{
+    providers::{Completion, FinishReason, Provider, Usage, stream::StreamFormat},
+    settings::OllamaSettings,
+};
 
 pub struct OllamaProvider {
     config: OllamaSettings,
@@ -12,6 +15,14 @@ impl Provider for OllamaProvider {
         debug!("Create provider from {config:?}");
         Self { config }
     }
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
     fn build_url(&self) -> String {
         format!("{}/api/generate", self.config.url)
     }
@@ -29,13 +40,56 @@ impl Provider for OllamaProvider {
         }))
     }
 
-    fn parse_response(&self, response: serde_json::Value) -> anyhow::Result<String> {
+    fn build_structured_request_body(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let mut body = self.build_request_body(prompt)?;
+        body["format"] = schema.clone();
+        Ok(body)
+    }
+
+    fn parse_response(&self, response: serde_json::Value) -> anyhow::Result<Completion> {
         let generated_text = response
             .get("response")
             .and_then(|v| v.as_str())
             .unwrap_or("")
             .to_string();
 
-        Ok(generated_text)
+        let finish_reason = match response.get("done_reason").and_then(|v| v.as_str()) {
+            Some("stop") => FinishReason::Stop,
+            Some("length") => FinishReason::MaxTokens,
+            Some(reason) => FinishReason::Other(reason.to_string()),
+            None => FinishReason::Unknown,
+        };
+
+        // Ollama only reports token counts on the final response
+        let usage = response
+            .get("prompt_eval_count")
+            .and_then(|count| count.as_u64())
+            .zip(response.get("eval_count").and_then(|count| count.as_u64()))
+            .map(|(input_tokens, output_tokens)| Usage {
+                input_tokens,
+                output_tokens,
+            });
+
+        Ok(Completion {
+            text: generated_text,
+            finish_reason,
+            usage,
+        })
+    }
+
+    fn stream_format(&self) -> StreamFormat {
+        StreamFormat::Ndjson
+    }
+
+    fn parse_stream_event(&self, event: serde_json::Value) -> anyhow::Result<Completion> {
+        if let Some(error) = event.get("error") {
+            return Err(anyhow::anyhow!("Stream failed: {error}"));
+        }
+
+        self.parse_response(event)
     }
 }
@@ -1,7 +1,17 @@
+use std::time::Duration;
+
 use log::debug;
+use reqwest::StatusCode;
 use secrecy::ExposeSecret;
 
-use crate::{providers::Provider, settings::OpenAISettings};
+use crate::{
+    providers::{
+        Completion, FinishReason, Provider, Request, Usage,
+        error::{ProviderError, error_message},
+        structured::SCHEMA_NAME,
+    },
+    settings::OpenAISettings,
+};
 
 pub struct OpenAIProvider {
     config: OpenAISettings,
@@ -15,6 +25,14 @@ impl Provider for OpenAIProvider {
         Self { config }
     }
 
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
     fn build_url(&self) -> String {
         format!("{}/chat/completions", self.config.base_url)
     }
@@ -35,18 +53,128 @@ impl Provider for OpenAIProvider {
         }))
     }
 
-    fn parse_response(&self, response: serde_json::Value) -> anyhow::Result<String> {
-        let generated_text = response
+    fn build_structured_request_body(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let mut body = self.build_request_body(prompt)?;
+        body["response_format"] = serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": SCHEMA_NAME,
+                "strict": true,
+                "schema": schema
+            }
+        });
+        Ok(body)
+    }
+
+    fn parse_response(&self, response: serde_json::Value) -> anyhow::Result<Completion> {
+        let choice = response
             .get("choices")
             .and_then(|choices| choices.as_array())
-            .and_then(|choices| choices.first())
+            .and_then(|choices| choices.first());
+
+        let generated_text = choice
             .and_then(|choice| choice.get("message"))
             .and_then(|message| message.get("content"))
             .and_then(|content| content.as_str())
             .unwrap_or("")
             .to_string();
 
-        Ok(generated_text)
+        Ok(Completion {
+            text: generated_text,
+            finish_reason: finish_reason(choice),
+            usage: usage(&response),
+        })
+    }
+
+    fn build_continuation_body(
+        &self,
+        prompt: &str,
+        partial: &str,
+    ) -> anyhow::Result<Option<serde_json::Value>> {
+        let mut body = self.build_request_body(prompt)?;
+        body["messages"] = serde_json::json!([
+            {
+                "role": "user",
+                "content": prompt
+            },
+            {
+                "role": "assistant",
+                "content": partial
+            },
+            {
+                "role": "user",
+                "content": "Continue exactly where you left off. Do not repeat any previous text."
+            }
+        ]);
+
+        Ok(Some(body))
+    }
+
+    fn build_stream_request_body(
+        &self,
+        request: &Request,
+        prompt: &str,
+    ) -> anyhow::Result<serde_json::Value> {
+        let mut body = self.build_request_body(prompt)?;
+        body["stream"] = serde_json::Value::Bool(true);
+        // Token usage is only sent, in a final chunk without choices, when asked for. Some
+        // OpenAI compatible servers reject the option, so it is left out unless needed.
+        if request.report_usage {
+            body["stream_options"] = serde_json::json!({ "include_usage": true });
+        }
+        Ok(body)
+    }
+
+    fn parse_stream_event(&self, event: serde_json::Value) -> anyhow::Result<Completion> {
+        if let Some(error) = event.get("error") {
+            return Err(anyhow::anyhow!("Stream failed: {error}"));
+        }
+
+        let choice = event
+            .get("choices")
+            .and_then(|choices| choices.as_array())
+            .and_then(|choices| choices.first());
+
+        Ok(Completion {
+            text: choice
+                .and_then(|choice| choice.get("delta"))
+                .and_then(|delta| delta.get("content"))
+                .and_then(|content| content.as_str())
+                .unwrap_or("")
+                .to_string(),
+            finish_reason: finish_reason(choice),
+            usage: usage(&event),
+        })
+    }
+
+    fn parse_error(
+        &self,
+        status: StatusCode,
+        body: &str,
+        retry_after: Option<Duration>,
+    ) -> ProviderError {
+        let json: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+        let message = error_message(&json, body);
+        let code = json
+            .get("error")
+            .and_then(|error| error.get("code"))
+            .and_then(|code| code.as_str());
+
+        match code {
+            Some("context_length_exceeded") => ProviderError::ContextLengthExceeded { message },
+            Some("rate_limit_exceeded") => ProviderError::RateLimited {
+                message,
+                retry_after,
+            },
+            Some("invalid_api_key") => ProviderError::Auth { message },
+            // Retrying will not refill an exhausted billing quota
+            Some("insufficient_quota") => ProviderError::Request { status, message },
+            _ => ProviderError::from_status(status, message, retry_after),
+        }
     }
 
     fn get_client(&self) -> reqwest::blocking::Client {
@@ -70,3 +198,24 @@ impl Provider for OpenAIProvider {
             .unwrap_or_else(|_| reqwest::blocking::Client::new())
     }
 }
+
+fn finish_reason(choice: Option<&serde_json::Value>) -> FinishReason {
+    match choice
+        .and_then(|choice| choice.get("finish_reason"))
+        .and_then(|reason| reason.as_str())
+    {
+        Some("stop") => FinishReason::Stop,
+        Some("length") => FinishReason::MaxTokens,
+        Some("content_filter") => FinishReason::ContentFilter(String::from("content_filter")),
+        Some(reason) => FinishReason::Other(reason.to_string()),
+        None => FinishReason::Unknown,
+    }
+}
+
+fn usage(response: &serde_json::Value) -> Option<Usage> {
+    let usage = response.get("usage")?;
+    Some(Usage {
+        input_tokens: usage.get("prompt_tokens")?.as_u64()?,
+        output_tokens: usage.get("completion_tokens")?.as_u64()?,
+    })
+}
@@ -1,7 +1,16 @@
-use std::process::Command;
-
 use anyhow::{Result, anyhow};
 use indoc::indoc;
+use log::{info, warn};
+
+use crate::{
+    budget,
+    git::{Diff, DiffMode, Pathspec, Repository, Skipped},
+    providers::{Output, Request},
+    redact,
+};
+
+/// Tokens set aside for the list of elided files when budgeting the diff
+const ELIDED_RESERVE: usize = 256;
 
 pub struct Prompt;
 
@@ -43,58 +52,282 @@ impl Prompt {
         - Not include punctuation at the end
         Don't include your own thought process. The output should be just the PR title."#
     };
-    pub fn render(
-        base: &str,
-        head: &str,
-        exclude: &[&str],
-        role: Option<&str>,
-        directive: Option<&str>,
-        template: Option<&str>,
-        is_title: bool,
-    ) -> Result<String> {
-        let default_directive = if is_title {
-            Self::DEFAULT_TITLE_DIRECTIVE
-        } else {
-            Self::DEFAULT_DIRECTIVE
+    pub const DEFAULT_COMMIT_DIRECTIVE: &str = indoc! {
+        r#"Analyze this git diff and write a commit message for it. The message should:
+        - Start with a subject line of 50 characters or less in imperative mood (e.g., "Add feature" not "Added feature")
+        - Not end the subject line with punctuation
+        - Follow the subject with a blank line and a short body explaining what changed and why, wrapped at 72 characters
+        Don't include your own thought process or wrap the message in code fences. The output should be just the commit message."#
+    };
+    pub const DEFAULT_CONVENTIONAL_COMMIT_DIRECTIVE: &str = indoc! {
+        r#"Analyze this git diff and write a commit message for it following the Conventional Commits specification. The message should:
+        - Start with a subject line of the form `<type>(<optional scope>): <description>` where type is one of feat, fix, docs, style, refactor, perf, test, build, ci or chore
+        - Use imperative mood in the description and keep the subject line to 72 characters or less
+        - Follow the subject with a blank line and a short body explaining what changed and why, wrapped at 72 characters
+        - Mark breaking changes with `!` after the type and a `BREAKING CHANGE:` footer
+        Don't include your own thought process or wrap the message in code fences. The output should be just the commit message."#
+    };
+    pub const DEFAULT_RELEASE_DIRECTIVE: &str = indoc! {
+        r#"Write release notes from the commits and merged pull requests below, in Keep a Changelog style.
+        Group the changes under these markdown headings, in this order, leaving out any heading with no changes:
+        ### Breaking Changes, ### Added, ### Fixed, ### Changed
+        Put new features under Added, bug fixes under Fixed and refactors, dependency updates, docs and chores under Changed.
+        Write one short bullet point per change in past tense and keep ticket and pull request references.
+        Do not include a version heading. Start directly with the first heading, no conversational preamble."#
+    };
+    pub const DEFAULT_REVIEW_DIRECTIVE: &str = indoc! {
+        r#"Review the changes in this git diff as an experienced code reviewer. Look for bugs, security problems, missing
+        error handling, performance issues and unclear code. Only comment on lines added or changed in the diff.
+        Respond with only a JSON object of this shape, no code fences or other text:
+        {"findings": [{"file": "<path>", "start_line": <first line>, "end_line": <last line>, "severity": "error" | "warning" | "note", "message": "<problem and suggested fix>"}]}
+        Line numbers refer to the new version of the file. Use an empty findings array when there is nothing to report."#
+    };
+    pub const DEFAULT_STRUCTURED_DIRECTIVE: &str = indoc! {
+        r#"Write a pull request title and description for the changes made in the diff and respond with JSON.
+        The title should be 50 characters or less, in imperative mood and without punctuation at the end.
+        The description should be a professional markdown summary following the template. Do not write code.
+        Classify the change as feature, bug, chore or docs and list each breaking change with its migration steps."#
+    };
+    pub const DEFAULT_CHUNK_DIRECTIVE: &str = indoc! {
+        r#"This diff is one part of a larger change. Summarize what changed in it in a few short bullet points.
+        Be specific about what was modified and mention anything that looks like a breaking change. Do not write code.
+        Start directly with the bullet points, no conversational preamble."#
+    };
+
+    pub fn render(request: &Request) -> Result<String> {
+        if request.output == Output::ReleaseNotes {
+            return Self::render_release(request);
+        }
+
+        let diff = Self::diff(request)?;
+        Self::render_with_diff(request, &diff)
+    }
+
+    /// Render the prompt for release notes from the commit history alone, the diff
+    /// between two releases is rarely useful and seldom fits the context window
+    pub fn render_release(request: &Request) -> Result<String> {
+        let mut history = Self::commit_log(request)?;
+        if history.is_empty() {
+            return Err(anyhow!(
+                "No commits between `{}` and `{}`",
+                request.base,
+                request.head
+            ));
+        }
+
+        let header = Self::header(request);
+        if let Some(max_tokens) = request.max_prompt_tokens {
+            let max_chars = max_tokens.saturating_sub(budget::estimate_tokens(&header)) * 4;
+            if let Some((end, _)) = history.char_indices().nth(max_chars) {
+                warn!("Commit history was truncated to fit the prompt budget");
+                history.truncate(end);
+                history.push_str("\n[TRUNCATED]\n");
+            }
+        }
+
+        Ok(header + &history)
+    }
+
+    /// Render the prompt around an already collected diff
+    pub fn render_with_diff(request: &Request, diff: &Diff) -> Result<String> {
+        let header = Self::header(request) + &Self::history(request)?;
+
+        let Some(max_tokens) = request.max_prompt_tokens else {
+            return Ok(format!(
+                "{header}[DIFF]\n{}\n{}",
+                diff.to_patch(),
+                Self::skipped(&diff.skipped)
+            ));
         };
 
-        Ok(format!(
+        let budgeted = budget::fit_to_budget(
+            diff,
+            max_tokens.saturating_sub(budget::estimate_tokens(&header) + ELIDED_RESERVE),
+        );
+        if budgeted.diff.is_empty() {
+            return Err(anyhow!(
+                "The diff does not fit within the prompt budget of {max_tokens} tokens"
+            ));
+        }
+
+        let mut prompt = format!("{header}[DIFF]\n{}\n", budgeted.diff);
+        if !budgeted.elided.is_empty() {
+            warn!(
+                "Elided {} file(s) from the diff to fit the prompt budget",
+                budgeted.elided.len()
+            );
+            prompt.push_str(indoc! {"
+                [ELIDED]
+                The following files were changed but omitted from the diff to fit the context window:
+            "});
+            for elided in budgeted.elided {
+                prompt.push_str(&format!("- {elided}\n"));
+            }
+        }
+        prompt.push_str(&Self::skipped(&diff.skipped));
+
+        Ok(prompt)
+    }
+
+    /// Render the prompt asking for a summary of a single chunk of a larger diff
+    pub fn render_chunk(request: &Request, diff: &str) -> String {
+        format!(
             indoc! {"
             [ROLE]
             {role}
             [DIRECTIVE]
             {directive}
-            [PULL_REQUEST_TEMPLATE]
-            {template}
             [DIFF]
             {diff}
             "},
-            diff = Self::get_git_diff(base, head, exclude)?,
-            role = role.unwrap_or(Self::DEFAULT_ROLE),
-            directive = directive.unwrap_or(default_directive),
-            template = template.unwrap_or(Self::DEFAULT_TEMPLATE)
-        ))
+            role = request.role.as_deref().unwrap_or(Self::DEFAULT_ROLE),
+            directive = Self::DEFAULT_CHUNK_DIRECTIVE,
+            diff = diff
+        )
     }
 
-    fn get_git_diff(base: &str, head: &str, exclude: &[&str]) -> Result<String> {
-        let mut cmd = Command::new("git");
+    /// Render the final prompt combining the summaries of each chunk of the diff
+    pub fn render_summaries(
+        request: &Request,
+        summaries: &[(String, String)],
+        skipped: &[Skipped],
+    ) -> Result<String> {
+        let mut prompt = Self::header(request) + &Self::history(request)?;
+        prompt.push_str(indoc! {"
+            [CHANGE_SUMMARIES]
+            The diff was too large to include directly. These are summaries of each part of it:
+        "});
+        for (label, summary) in summaries {
+            prompt.push_str(&format!("### {label}\n{}\n", summary.trim()));
+        }
+        prompt.push_str(&Self::skipped(skipped));
 
-        cmd.arg("diff").arg(base).arg(head);
+        Ok(prompt)
+    }
 
-        let output = cmd.args(["--", &exclude.join(" ")]).output()?;
+    /// Collect the diff described by the request
+    pub fn diff(request: &Request) -> Result<Diff> {
+        let pathspec = Pathspec::from_filters(&request.include, &request.exclude)?;
+        let repo = Repository::open()?;
+        let mut diff = match request.mode {
+            DiffMode::Range if request.two_dot => {
+                repo.diff(&request.base, &request.head, &pathspec)?
+            }
+            DiffMode::Range => {
+                let merge_base = repo.merge_base(&request.base, &request.head)?;
+                info!(
+                    "Comparing `{}` against merge base {merge_base} with `{}`",
+                    request.head, request.base
+                );
+                repo.diff(&merge_base.to_string(), &request.head, &pathspec)?
+            }
+            mode => repo.diff_uncommitted(mode, &request.base, &pathspec)?,
+        };
+        if !diff.skipped.is_empty() {
+            info!(
+                "Skipped {} file(s): {}",
+                diff.skipped.len(),
+                diff.skipped
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        if let Some(settings) = &request.redact {
+            redact::redact_diff(&mut diff, settings)?;
+        }
+        Ok(diff)
+    }
 
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Git diff failed: {}", error);
+    /// Render the messages of the commits being described so the model can explain why
+    /// the changes were made. Empty unless requested or when describing uncommitted work.
+    pub fn history(request: &Request) -> Result<String> {
+        if !request.commits || request.mode != DiffMode::Range {
+            return Ok(String::new());
         }
+        Self::commit_log(request)
+    }
 
-        let diff = String::from_utf8(output.stdout)?;
+    fn commit_log(request: &Request) -> Result<String> {
+        let commits = Repository::open()?.commits(&request.base, &request.head)?;
+        if commits.is_empty() {
+            return Ok(String::new());
+        }
 
-        if diff.is_empty() {
-            return Err(anyhow!("No differences between `{base}` and `{head}`"));
+        let mut history = String::from("[COMMITS]\n");
+        for commit in &commits {
+            history.push_str(&format!("- {}\n", commit.summary));
+            for line in commit.body.lines() {
+                if line.is_empty() {
+                    history.push('\n');
+                } else {
+                    history.push_str(&format!("  {line}\n"));
+                }
+            }
+            for (key, value) in &commit.trailers {
+                history.push_str(&format!("  {key}: {value}\n"));
+            }
         }
 
-        Ok(diff)
+        match &request.redact {
+            Some(settings) => redact::redact_text(&history, "commit messages", settings),
+            None => Ok(history),
+        }
+    }
+
+    /// List the files removed by `.praiignore` and `.gitattributes` so the model knows they changed
+    fn skipped(skipped: &[Skipped]) -> String {
+        if skipped.is_empty() {
+            return String::new();
+        }
+
+        let paths: Vec<String> = skipped.iter().map(ToString::to_string).collect();
+        format!(
+            "[SKIPPED]\nThese files also changed but were excluded from the diff: {}\n",
+            paths.join(", ")
+        )
+    }
+
+    fn header(request: &Request) -> String {
+        let role = request.role.as_deref().unwrap_or(Self::DEFAULT_ROLE);
+        let default_directive = match request.output {
+            Output::Description => Self::DEFAULT_DIRECTIVE,
+            Output::Title => Self::DEFAULT_TITLE_DIRECTIVE,
+            Output::CommitMessage {
+                conventional: false,
+            } => Self::DEFAULT_COMMIT_DIRECTIVE,
+            Output::CommitMessage { conventional: true } => {
+                Self::DEFAULT_CONVENTIONAL_COMMIT_DIRECTIVE
+            }
+            Output::ReleaseNotes => Self::DEFAULT_RELEASE_DIRECTIVE,
+            Output::Review => Self::DEFAULT_REVIEW_DIRECTIVE,
+            Output::Structured => Self::DEFAULT_STRUCTURED_DIRECTIVE,
+        };
+        let directive = request.directive.as_deref().unwrap_or(default_directive);
+
+        // The pull request template only shapes descriptions and titles
+        if let Output::CommitMessage { .. } | Output::ReleaseNotes | Output::Review = request.output
+        {
+            return format!("[ROLE]\n{role}\n[DIRECTIVE]\n{directive}\n");
+        }
+
+        format!(
+            indoc! {"
+            [ROLE]
+            {role}
+            [DIRECTIVE]
+            {directive}
+            [PULL_REQUEST_TEMPLATE]
+            {template}
+            "},
+            role = role,
+            directive = directive,
+            template = request
+                .template
+                .as_deref()
+                .unwrap_or(Self::DEFAULT_TEMPLATE)
+        )
     }
 }
 
@@ -108,9 +341,12 @@ mod tests {
 
     #[test]
     fn test_prompt() {
-        let prompt = Prompt::render("683ddd6", "d2bbcc5", &[":!*.lock"], None, None, None, false)
-            .unwrap()
-            .replace(" \n", "\n");
+        let request = Request::builder()
+            .base(String::from("683ddd6"))
+            .head(String::from("d2bbcc5"))
+            .exclude(vec![String::from(":!*.lock")])
+            .build();
+        let prompt = Prompt::render(&request).unwrap().replace(" \n", "\n");
 
         assert_str_eq!(EXPECTED.trim(), prompt.as_str().trim());
     }
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use log::warn;
+
+use crate::{providers::error::ProviderError, settings::RetrySettings};
+
+/// Run `f` until it succeeds, fails with a non-retryable error, or runs out of attempts
+pub fn with_retry<T, F>(settings: &RetrySettings, mut f: F) -> Result<T, ProviderError>
+where
+    F: FnMut() -> Result<T, ProviderError>,
+{
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(e) if e.is_retryable() && attempt < settings.max_attempts => {
+                let delay = delay_for(settings, attempt, e.retry_after());
+                warn!(
+                    "{e}. Retrying in {:.1}s (attempt {}/{})",
+                    delay.as_secs_f32(),
+                    attempt + 1,
+                    settings.max_attempts
+                );
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Exponential backoff with jitter, or the provider's requested delay when honored
+fn delay_for(settings: &RetrySettings, attempt: u32, retry_after: Option<Duration>) -> Duration {
+    let max_delay = Duration::from_millis(settings.max_delay_ms);
+
+    if let Some(retry_after) = retry_after.filter(|_| settings.respect_retry_after) {
+        return retry_after.min(max_delay);
+    }
+
+    let backoff = settings
+        .base_delay_ms
+        .saturating_mul(1 << (attempt - 1).min(16));
+    let jitter = rand::random_range(0..=backoff / 4);
+
+    Duration::from_millis(backoff.saturating_add(jitter)).min(max_delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+    use reqwest::StatusCode;
+
+    fn settings() -> RetrySettings {
+        RetrySettings {
+            max_attempts: 3,
+            base_delay_ms: 1,
+            max_delay_ms: 10,
+            respect_retry_after: true,
+        }
+    }
+
+    #[test]
+    fn test_retries_retryable_errors() {
+        let mut calls = 0;
+        let result = with_retry(&settings(), || {
+            calls += 1;
+            match calls {
+                1 => Err(ProviderError::from_status(
+                    StatusCode::from_u16(529).unwrap(),
+                    String::from("Overloaded"),
+                    None,
+                )),
+                _ => Ok(calls),
+            }
+        });
+
+        assert_eq!(2, result.unwrap());
+    }
+
+    #[test]
+    fn test_stops_on_fatal_errors() {
+        let mut calls = 0;
+        let result: Result<(), _> = with_retry(&settings(), || {
+            calls += 1;
+            Err(ProviderError::Auth {
+                message: String::from("invalid x-api-key"),
+            })
+        });
+
+        assert!(matches!(result, Err(ProviderError::Auth { .. })));
+        assert_eq!(1, calls);
+    }
+
+    #[test]
+    fn test_delay_honors_retry_after() {
+        let delay = delay_for(&settings(), 1, Some(Duration::from_millis(5)));
+        assert_eq!(Duration::from_millis(5), delay);
+
+        let delay = delay_for(&settings(), 1, Some(Duration::from_secs(60)));
+        assert_eq!(Duration::from_millis(10), delay);
+    }
+}
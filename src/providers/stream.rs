@@ -0,0 +1,230 @@
+use std::io::BufRead;
+
+use anyhow::Result;
+
+/// Wire format used by a provider when streaming tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// Server-sent events where each `data:` payload is a JSON document
+    Sse,
+    /// Newline delimited JSON where each line is a JSON document
+    Ndjson,
+}
+
+/// Read JSON events from a streamed response body and hand each one to `f`
+pub fn for_each_event<R, F>(reader: R, format: StreamFormat, mut f: F) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(serde_json::Value) -> Result<()>,
+{
+    match format {
+        StreamFormat::Sse => {
+            let mut data = String::new();
+            for line in reader.lines() {
+                let line = line?;
+                let line = line.trim_end_matches('\r');
+
+                if line.is_empty() {
+                    dispatch_sse(&mut data, &mut f)?;
+                } else if let Some(payload) = line.strip_prefix("data:") {
+                    if !data.is_empty() {
+                        data.push('\n');
+                    }
+                    data.push_str(payload.strip_prefix(' ').unwrap_or(payload));
+                }
+            }
+            dispatch_sse(&mut data, &mut f)
+        }
+        StreamFormat::Ndjson => {
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                f(serde_json::from_str(&line)?)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn dispatch_sse<F>(data: &mut String, f: &mut F) -> Result<()>
+where
+    F: FnMut(serde_json::Value) -> Result<()>,
+{
+    let payload = std::mem::take(data);
+    match payload.trim() {
+        "" | "[DONE]" => Ok(()),
+        payload => f(serde_json::from_str(payload)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        providers::{
+            FinishReason, Provider, Request, Usage, ollama::OllamaProvider, openai::OpenAIProvider,
+        },
+        settings::{OllamaSettings, OpenAISettings},
+    };
+
+    fn request() -> Request {
+        Request::builder()
+            .base(String::from("main"))
+            .head(String::from("HEAD"))
+            .exclude(vec![])
+            .build()
+    }
+
+    #[test]
+    fn test_sse_events() {
+        let body = indoc! {r#"
+            event: content_block_delta
+            data: {"delta":{"text":"Hello"}}
+
+            : keep-alive
+
+            data: {"delta":
+            data: {"text":" world"}}
+
+            data: [DONE]
+        "#};
+
+        let mut events = Vec::new();
+        for_each_event(body.as_bytes(), StreamFormat::Sse, |e| {
+            events.push(e);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                serde_json::json!({"delta": {"text": "Hello"}}),
+                serde_json::json!({"delta": {"text": " world"}}),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn test_stream_openai_sse() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"stream": true, "stream_options": {"include_usage": true}}"#.into(),
+            ))
+            .with_header("content-type", "text/event-stream")
+            .with_body(indoc! {r#"
+                data: {"choices":[{"delta":{"role":"assistant"}}]}
+
+                data: {"choices":[{"delta":{"content":"Add"}}]}
+
+                data: {"choices":[{"delta":{"content":" streaming"},"finish_reason":"stop"}]}
+
+                data: {"choices":[],"usage":{"prompt_tokens":900,"completion_tokens":2}}
+
+                data: [DONE]
+
+            "#})
+            .create();
+
+        let provider = OpenAIProvider::from_config(OpenAISettings {
+            model: String::from("gpt-4"),
+            api_key: "key".into(),
+            base_url: server.url(),
+            max_tokens: 500,
+            temperature: 0.3,
+            top_p: 0.9,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+        });
+
+        let mut request = request();
+        request.report_usage = true;
+        let mut tokens = Vec::new();
+        let completion = provider
+            .stream_prompt(&request, "prompt", &mut |t| tokens.push(t.to_string()))
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(vec!["Add", " streaming"], tokens);
+        assert_eq!("Add streaming", completion.text);
+        assert_eq!(FinishReason::Stop, completion.finish_reason);
+        let usage = Usage {
+            input_tokens: 900,
+            output_tokens: 2,
+        };
+        assert_eq!(Some(usage), completion.usage);
+        assert_eq!(vec![(None, usage)], request.meter.totals());
+    }
+
+    #[test]
+    fn test_stream_usage_only_when_reported() {
+        let provider = OpenAIProvider::from_config(OpenAISettings {
+            model: String::from("gpt-4"),
+            api_key: "key".into(),
+            base_url: String::from("http://localhost"),
+            max_tokens: 500,
+            temperature: 0.3,
+            top_p: 0.9,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+        });
+
+        let mut request = request();
+        let body = provider
+            .build_stream_request_body(&request, "prompt")
+            .unwrap();
+        assert_eq!(None, body.get("stream_options"));
+
+        request.report_usage = true;
+        let body = provider
+            .build_stream_request_body(&request, "prompt")
+            .unwrap();
+        assert_eq!(
+            Some(&serde_json::json!({ "include_usage": true })),
+            body.get("stream_options")
+        );
+    }
+
+    #[test]
+    fn test_stream_ollama_ndjson() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"stream": true}"#.into(),
+            ))
+            .with_header("content-type", "application/x-ndjson")
+            .with_body(indoc! {r#"
+                {"response":"Fix","done":false}
+                {"response":" parser","done":false}
+                {"response":"","done":true,"done_reason":"length"}
+            "#})
+            .create();
+
+        let provider = OllamaProvider::from_config(OllamaSettings {
+            url: server.url(),
+            model: String::from("codegemma:7b"),
+            temperature: 0.3,
+            top_p: 0.9,
+            num_predict: 500,
+        });
+
+        let mut tokens = Vec::new();
+        let completion = provider
+            .stream_prompt(&request(), "prompt", &mut |t| tokens.push(t.to_string()))
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(vec!["Fix", " parser"], tokens);
+        assert_eq!("Fix parser", completion.text);
+        assert_eq!(FinishReason::MaxTokens, completion.finish_reason);
+    }
+}
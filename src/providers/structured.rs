@@ -0,0 +1,210 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::providers::Usage;
+
+/// Name of the schema, and of the tool Anthropic is forced to call
+pub const SCHEMA_NAME: &str = "pull_request";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeOfChange {
+    Feature,
+    Bug,
+    Chore,
+    Docs,
+}
+
+/// The fields the model fills in for `--format json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PullRequest {
+    pub title: String,
+    pub description: String,
+    pub type_of_change: TypeOfChange,
+    pub breaking_changes: Vec<String>,
+}
+
+impl PullRequest {
+    /// The title, a blank line and the description, like a commit message
+    pub fn to_text(&self) -> String {
+        format!("{}\n\n{}", self.title.trim(), self.description.trim())
+    }
+
+    /// What `--combined` prints, and the description to write to the `--body-file` when
+    /// `split_body` is set. Split off, only the title is printed.
+    pub fn to_combined(&self, split_body: bool) -> (String, Option<String>) {
+        if split_body {
+            (
+                self.title.trim().to_string(),
+                Some(format!("{}\n", self.description.trim())),
+            )
+        } else {
+            (self.to_text(), None)
+        }
+    }
+}
+
+/// A generated pull request along with where it came from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Structured {
+    #[serde(flatten)]
+    pub pull_request: PullRequest,
+    pub provider: String,
+    pub model: String,
+    pub usage: Option<Usage>,
+}
+
+/// JSON schema for [`PullRequest`], strict enough for OpenAI's structured outputs
+pub fn schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "title": {
+                "type": "string",
+                "description": "Concise pull request title in imperative mood, 50 characters or less"
+            },
+            "description": {
+                "type": "string",
+                "description": "Markdown pull request description following the template"
+            },
+            "type_of_change": {
+                "type": "string",
+                "enum": ["feature", "bug", "chore", "docs"]
+            },
+            "breaking_changes": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Each breaking change with migration steps, empty when there are none"
+            }
+        },
+        "required": ["title", "description", "type_of_change", "breaking_changes"],
+        "additionalProperties": false
+    })
+}
+
+/// Convert a JSON schema to the OpenAPI subset Gemini accepts for `responseSchema`
+pub fn to_openapi(schema: &Value) -> Value {
+    match schema {
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .filter(|(key, _)| *key != "additionalProperties")
+                .map(|(key, value)| match (key.as_str(), value) {
+                    ("type", Value::String(kind)) => {
+                        (key.clone(), Value::String(kind.to_uppercase()))
+                    }
+                    // Property names are not schemas, keep them as they are
+                    ("properties", Value::Object(properties)) => (
+                        key.clone(),
+                        Value::Object(
+                            properties
+                                .iter()
+                                .map(|(name, schema)| (name.clone(), to_openapi(schema)))
+                                .collect(),
+                        ),
+                    ),
+                    _ => (key.clone(), to_openapi(value)),
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(to_openapi).collect()),
+        value => value.clone(),
+    }
+}
+
+/// The outermost JSON object in a response, ignoring code fences or prose around it
+pub fn json_object(text: &str) -> Option<&str> {
+    let start = text.find('{')?;
+    let end = text.rfind('}').filter(|&end| end > start)?;
+    Some(&text[start..=end])
+}
+
+/// Parse and validate the model's response against [`schema`]
+pub fn parse(text: &str) -> Result<PullRequest> {
+    let object = json_object(text).ok_or(anyhow!("The response did not contain a JSON object"))?;
+    let pull_request: PullRequest = serde_json::from_str(object)
+        .context("The response did not match the pull request schema")?;
+
+    if pull_request.title.trim().is_empty() {
+        return Err(anyhow!("The response has an empty title"));
+    }
+    if pull_request.description.trim().is_empty() {
+        return Err(anyhow!("The response has an empty description"));
+    }
+
+    Ok(pull_request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse() {
+        let pull_request = parse(
+            r#"```json
+            {"title": "Add JSON output", "description": "Adds `--format json`.", "type_of_change": "feature", "breaking_changes": []}
+            ```"#,
+        )
+        .unwrap();
+        assert_eq!("Add JSON output", pull_request.title);
+        assert_eq!(TypeOfChange::Feature, pull_request.type_of_change);
+
+        assert!(parse("Add JSON output").is_err());
+        assert!(
+            parse(r#"{"title": "", "description": "x", "type_of_change": "docs", "breaking_changes": []}"#)
+                .is_err()
+        );
+        assert!(
+            parse(r#"{"title": "x", "description": "x", "type_of_change": "refactor", "breaking_changes": []}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_to_combined() {
+        let pull_request = PullRequest {
+            title: String::from(" Add combined output\n"),
+            description: String::from("\nGenerates both from one request.\n\n"),
+            type_of_change: TypeOfChange::Feature,
+            breaking_changes: Vec::new(),
+        };
+        let text = "Add combined output\n\nGenerates both from one request.";
+
+        assert_eq!(text, pull_request.to_text());
+        assert_eq!((text.to_string(), None), pull_request.to_combined(false));
+        assert_eq!(
+            (
+                String::from("Add combined output"),
+                Some(String::from("Generates both from one request.\n"))
+            ),
+            pull_request.to_combined(true)
+        );
+    }
+
+    #[test]
+    fn test_to_openapi() {
+        assert_eq!(
+            json!({
+                "type": "OBJECT",
+                "properties": {
+                    "type": { "type": "STRING", "enum": ["a", "b"] },
+                    "items": { "type": "ARRAY", "items": { "type": "STRING" } }
+                },
+                "required": ["type", "items"]
+            }),
+            to_openapi(&json!({
+                "type": "object",
+                "properties": {
+                    "type": { "type": "string", "enum": ["a", "b"] },
+                    "items": { "type": "array", "items": { "type": "string" } }
+                },
+                "required": ["type", "items"],
+                "additionalProperties": false
+            }))
+        );
+    }
+}
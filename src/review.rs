@@ -0,0 +1,190 @@
+use std::fmt;
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::providers::structured;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+            Self::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A single problem the model found in the changed code
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Finding {
+    pub file: String,
+    pub start_line: u32,
+    pub end_line: u32,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.start_line)?;
+        if self.end_line != self.start_line {
+            write!(f, "-{}", self.end_line)?;
+        }
+        write!(f, ": {}: {}", self.severity, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Review {
+    pub findings: Vec<Finding>,
+}
+
+impl Review {
+    /// Parse the model's response, tolerating code fences and surrounding prose, and
+    /// reject findings which do not point at a file and a valid line range
+    pub fn parse(text: &str) -> Result<Self> {
+        let object = structured::json_object(text)
+            .ok_or(anyhow!("The review response did not contain a JSON object"))?;
+
+        let mut review: Self = serde_json::from_str(object)
+            .context("The review response did not match the expected structure")?;
+
+        for (i, finding) in review.findings.iter_mut().enumerate() {
+            finding.file = finding.file.trim().trim_start_matches("b/").to_string();
+            if finding.file.is_empty() || finding.message.trim().is_empty() {
+                return Err(anyhow!("Finding {i} is missing a file or message"));
+            }
+            if finding.start_line == 0 || finding.end_line < finding.start_line {
+                return Err(anyhow!(
+                    "Finding {i} has an invalid line range {}-{}",
+                    finding.start_line,
+                    finding.end_line
+                ));
+            }
+        }
+        review
+            .findings
+            .sort_by(|a, b| (&a.file, a.start_line).cmp(&(&b.file, b.start_line)));
+
+        Ok(review)
+    }
+
+    /// One finding per line, like compiler diagnostics
+    pub fn to_text(&self) -> String {
+        if self.findings.is_empty() {
+            return String::from("No findings\n");
+        }
+        self.findings.iter().map(|f| format!("{f}\n")).collect()
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// A SARIF 2.1.0 log for code scanning dashboards
+    pub fn to_sarif(&self) -> Result<String> {
+        let results: Vec<_> = self
+            .findings
+            .iter()
+            .map(|f| {
+                json!({
+                    "ruleId": "prai-review",
+                    "level": f.severity.to_string(),
+                    "message": { "text": f.message },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": f.file },
+                            "region": { "startLine": f.start_line, "endLine": f.end_line }
+                        }
+                    }]
+                })
+            })
+            .collect();
+
+        let log = json!({
+            "$schema": SARIF_SCHEMA,
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "informationUri": env!("CARGO_PKG_HOMEPAGE"),
+                        "rules": [{
+                            "id": "prai-review",
+                            "shortDescription": { "text": "AI code review finding" }
+                        }]
+                    }
+                },
+                "results": results
+            }]
+        });
+
+        Ok(serde_json::to_string_pretty(&log)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_review() {
+        let review = Review::parse(indoc! {r#"
+            ```json
+            {"findings": [
+                {"file": "src/main.rs", "start_line": 12, "end_line": 14, "severity": "warning", "message": "Unwrap on user input"},
+                {"file": "b/src/budget.rs", "start_line": 3, "end_line": 3, "severity": "error", "message": "Division by zero"}
+            ]}
+            ```
+        "#})
+        .unwrap();
+
+        assert_eq!(
+            indoc! {"
+                src/budget.rs:3: error: Division by zero
+                src/main.rs:12-14: warning: Unwrap on user input
+            "},
+            review.to_text()
+        );
+
+        let sarif: serde_json::Value = serde_json::from_str(&review.to_sarif().unwrap()).unwrap();
+        let result = &sarif["runs"][0]["results"][1];
+        assert_eq!("warning", result["level"]);
+        assert_eq!(
+            12,
+            result["locations"][0]["physicalLocation"]["region"]["startLine"]
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_findings() {
+        assert!(Review::parse("Looks good to me!").is_err());
+        assert!(Review::parse(r#"{"findings": [{"file": "a.rs"}]}"#).is_err());
+        assert!(
+            Review::parse(
+                r#"{"findings": [{"file": "a.rs", "start_line": 9, "end_line": 2, "severity": "note", "message": "x"}]}"#
+            )
+            .is_err()
+        );
+        assert_eq!(
+            Review::default(),
+            Review::parse(r#"{"findings": []}"#).unwrap()
+        );
+    }
+}
@@ -1,27 +1,76 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Result, anyhow};
 use config::{ConfigBuilder, FileFormat, builder::DefaultState};
 use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
 
+use crate::{providers::Usage, redact};
+
 type DefaultConfigBuilder = ConfigBuilder<DefaultState>;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Settings {
     pub default: String,
+    #[serde(default = "Settings::default_exclude")]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub redact: RedactSettings,
+    #[serde(default, rename = "repository")]
+    repositories: Vec<RepositorySettings>,
+    #[serde(default, rename = "forge")]
+    pub forges: Vec<ForgeSettings>,
     #[serde(rename = "profile")]
     profiles: Vec<Profile>,
 }
 
 impl Settings {
-    pub fn get(self, profile: Option<String>) -> Result<Profile> {
-        let name = profile.unwrap_or(self.default);
+    fn default_exclude() -> Vec<String> {
+        vec![String::from(":!*.lock")]
+    }
+
+    /// Exclusions applied to every diff of the repository rooted at `root`:
+    /// the global list followed by any configured for that repository
+    pub fn excludes_for(&self, root: Option<&Path>) -> Vec<String> {
+        let root = root.and_then(|r| r.canonicalize().ok());
+        let repository = self
+            .repositories
+            .iter()
+            .filter(|r| root.is_some() && r.canonical_path() == root)
+            .flat_map(|r| r.exclude.iter());
+
+        self.exclude.iter().chain(repository).cloned().collect()
+    }
+
+    /// The profile called `profile`, or the default one. The profiles a fallback profile
+    /// tries are resolved into its `chain`.
+    pub fn get(&self, profile: Option<String>) -> Result<Profile> {
+        let name = profile.unwrap_or_else(|| self.default.clone());
+        let find = |name: &str| {
+            self.profiles
+                .iter()
+                .find(|p| p.name.eq(name))
+                .ok_or(anyhow!("Unable to find profile `{name}`"))
+        };
 
-        self.profiles
-            .into_iter()
-            .find(|p| p.name.eq(&name))
-            .ok_or(anyhow!("Unable to find profile `{name}`"))
+        let mut profile = find(&name)?.clone();
+        if let Provider::Fallback(fallback) = &mut profile.provider {
+            if fallback.profiles.is_empty() {
+                return Err(anyhow!("Fallback profile `{name}` lists no profiles"));
+            }
+            for member in &fallback.profiles {
+                let member = find(member)?;
+                if matches!(member.provider, Provider::Fallback(_)) {
+                    return Err(anyhow!(
+                        "Fallback profile `{name}` refers to fallback profile `{}`, chains cannot be nested",
+                        member.name
+                    ));
+                }
+                fallback.chain.push(member.clone());
+            }
+        }
+
+        Ok(profile)
     }
     pub fn from_path(path: &Path) -> anyhow::Result<Self> {
         Ok(Self::builder(path)?.build()?.try_deserialize()?)
@@ -45,22 +94,286 @@ where
     s.serialize_str("[REDACTED]")
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForgeKind {
+    GitHub,
+    GitLab,
+    #[serde(alias = "forgejo")]
+    Gitea,
+    /// Bitbucket Server and Data Center
+    Bitbucket,
+}
+
+/// Where and how to publish pull requests for remotes on `host`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ForgeSettings {
+    pub host: String,
+    /// Detected from well known hosts when unset
+    #[serde(rename = "type")]
+    pub kind: Option<ForgeKind>,
+    /// Root of the REST API, derived from the host when unset
+    pub api_url: Option<String>,
+    /// Used when the forge's token environment variables are not set
+    #[serde(default, skip_serializing)]
+    pub token: Option<SecretString>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
+pub struct RepositorySettings {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl RepositorySettings {
+    fn canonical_path(&self) -> Option<PathBuf> {
+        let path = match self.path.strip_prefix("~") {
+            Ok(rest) => dirs::home_dir()?.join(rest),
+            Err(_) => self.path.clone(),
+        };
+        path.canonicalize().ok()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Profile {
     pub name: String,
     pub role: Option<String>,
     pub directive: Option<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub retry: RetrySettings,
+    #[serde(default)]
+    pub max_continuations: u32,
+    pub context_window: Option<u32>,
+    pub max_prompt_tokens: Option<u32>,
+    /// Include commit messages in the prompt
+    #[serde(default)]
+    pub commits: bool,
+    #[serde(default)]
+    pub map_reduce: MapReduceSettings,
+    /// Replaces the global redaction settings for this profile
+    pub redact: Option<RedactSettings>,
+    /// What the model charges, for estimating the cost of a run
+    pub price: Option<PriceSettings>,
     #[serde(flatten)]
     pub provider: Provider,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Profile {
+    /// Tokens available for the rendered prompt. An explicit `max_prompt_tokens` wins,
+    /// otherwise the output tokens are reserved out of the `context_window`.
+    pub fn prompt_token_budget(&self) -> Option<usize> {
+        let budget = self
+            .max_prompt_tokens
+            .or_else(|| {
+                self.context_window
+                    .map(|window| window.saturating_sub(self.provider.max_output_tokens()))
+            })
+            .map(|tokens| tokens as usize);
+
+        // A prompt for a fallback chain has to fit every profile in it
+        match &self.provider {
+            Provider::Fallback(fallback) if budget.is_none() => fallback
+                .chain
+                .iter()
+                .filter_map(Profile::prompt_token_budget)
+                .min(),
+            _ => budget,
+        }
+    }
+
+    /// Redaction for prompts sent with this profile, `None` when it is turned off. A
+    /// fallback chain can send the prompt to any of its profiles, so the strictest
+    /// settings among them apply.
+    pub fn redact_settings(&self, global: &RedactSettings) -> Option<RedactSettings> {
+        let own = self.redact.clone().unwrap_or_else(|| global.clone());
+        match &self.provider {
+            Provider::Fallback(fallback) => redact::strictest(
+                std::iter::once(own).chain(
+                    fallback
+                        .chain
+                        .iter()
+                        .map(|member| member.redact.clone().unwrap_or_else(|| global.clone())),
+                ),
+            ),
+            _ => Some(own).filter(|redact| redact.enabled),
+        }
+    }
+}
+
+/// Prices in US dollars per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct PriceSettings {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl PriceSettings {
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input + usage.output_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RetrySettings {
+    #[serde(default = "RetrySettings::default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "RetrySettings::default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    #[serde(default = "RetrySettings::default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    #[serde(default = "RetrySettings::default_respect_retry_after")]
+    pub respect_retry_after: bool,
+}
+
+impl RetrySettings {
+    fn default_max_attempts() -> u32 {
+        3
+    }
+
+    fn default_base_delay_ms() -> u64 {
+        1000
+    }
+
+    fn default_max_delay_ms() -> u64 {
+        30_000
+    }
+
+    fn default_respect_retry_after() -> bool {
+        true
+    }
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: Self::default_max_attempts(),
+            base_delay_ms: Self::default_base_delay_ms(),
+            max_delay_ms: Self::default_max_delay_ms(),
+            respect_retry_after: Self::default_respect_retry_after(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MapReduceSettings {
+    #[serde(default = "MapReduceSettings::default_parallelism")]
+    pub parallelism: usize,
+    #[serde(default)]
+    pub chunk_by: ChunkBy,
+}
+
+impl MapReduceSettings {
+    fn default_parallelism() -> usize {
+        4
+    }
+}
+
+impl Default for MapReduceSettings {
+    fn default() -> Self {
+        Self {
+            parallelism: Self::default_parallelism(),
+            chunk_by: ChunkBy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RedactSettings {
+    #[serde(default = "RedactSettings::default_enabled")]
+    pub enabled: bool,
+    /// Fail with a report instead of sending the diff with placeholders
+    #[serde(default)]
+    pub abort: bool,
+    /// Bits per character above which a token is treated as a secret
+    #[serde(default = "RedactSettings::default_entropy_threshold")]
+    pub entropy_threshold: f64,
+    #[serde(default = "RedactSettings::default_min_entropy_length")]
+    pub min_entropy_length: usize,
+    /// Additional patterns redacted alongside the built in key formats
+    #[serde(default)]
+    pub patterns: Vec<RedactPattern>,
+}
+
+impl RedactSettings {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_entropy_threshold() -> f64 {
+        4.0
+    }
+
+    fn default_min_entropy_length() -> usize {
+        20
+    }
+}
+
+impl Default for RedactSettings {
+    fn default() -> Self {
+        Self {
+            enabled: Self::default_enabled(),
+            abort: false,
+            entropy_threshold: Self::default_entropy_threshold(),
+            min_entropy_length: Self::default_min_entropy_length(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RedactPattern {
+    pub name: String,
+    pub regex: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkBy {
+    #[default]
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "provider", rename_all = "lowercase")]
 pub enum Provider {
     Anthropic(AnthropicSettings),
     Ollama(OllamaSettings),
     OpenAI(OpenAISettings),
     Google(GoogleSettings),
+    Fallback(FallbackSettings),
+}
+
+impl Provider {
+    pub fn max_output_tokens(&self) -> u32 {
+        match self {
+            Self::Anthropic(config) => config.max_tokens,
+            Self::Ollama(config) => config.num_predict,
+            Self::OpenAI(config) => config.max_tokens,
+            Self::Google(config) => config.max_tokens,
+            Self::Fallback(config) => config
+                .chain
+                .iter()
+                .map(|p| p.provider.max_output_tokens())
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Try other profiles in order when one fails with an error that retrying could fix
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FallbackSettings {
+    /// Names of the profiles to try
+    pub profiles: Vec<String>,
+    /// The profiles named in `profiles`, filled in by [`Settings::get`]
+    #[serde(skip)]
+    pub chain: Vec<Profile>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -70,6 +383,8 @@ pub struct AnthropicSettings {
     pub model: String,
     #[serde(serialize_with = "serialize_secret")]
     pub api_key: SecretString,
+    #[serde(default = "AnthropicSettings::default_base_url")]
+    pub base_url: String,
     #[serde(default = "AnthropicSettings::default_max_tokens")]
     pub max_tokens: u32,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -83,12 +398,16 @@ impl AnthropicSettings {
         String::from("2023-06-01")
     }
 
+    fn default_base_url() -> String {
+        String::from("https://api.anthropic.com/v1")
+    }
+
     fn default_max_tokens() -> u32 {
         500
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct OllamaSettings {
     #[serde(default = "OllamaSettings::default_url")]
     pub url: String,
@@ -119,7 +438,7 @@ impl OllamaSettings {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct OpenAISettings {
     pub model: String,
     #[serde(serialize_with = "serialize_secret")]
@@ -164,7 +483,7 @@ impl OpenAISettings {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GoogleSettings {
     pub model: String,
     #[serde(serialize_with = "serialize_secret")]
@@ -196,3 +515,63 @@ impl GoogleSettings {
         0.9
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_redact_settings_of_fallback_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            indoc! {r#"
+                default = "auto"
+
+                [redact]
+                enabled = false
+
+                [[profile]]
+                name = "auto"
+                provider = "fallback"
+                profiles = ["ollama", "claude"]
+
+                [[profile]]
+                name = "ollama"
+                provider = "ollama"
+                url = "http://localhost:11434"
+                model = "codegemma:7b"
+
+                [[profile]]
+                name = "claude"
+                provider = "ollama"
+                url = "http://localhost:11435"
+                model = "llama3:8b"
+                redact = { enabled = true, patterns = [{ name = "acme-token", regex = "acme_[0-9a-f]{32}" }] }
+            "#},
+        )
+        .unwrap();
+        let settings = Settings::from_path(&path).unwrap();
+
+        let ollama = settings.get(Some(String::from("ollama"))).unwrap();
+        assert!(ollama.redact_settings(&settings.redact).is_none());
+
+        let redact = settings
+            .get(None)
+            .unwrap()
+            .redact_settings(&settings.redact)
+            .unwrap();
+        assert_eq!(
+            vec!["acme-token"],
+            redact
+                .patterns
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+}